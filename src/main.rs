@@ -5,87 +5,622 @@ use std::process::{Command, Stdio};
 use std::io::{BufRead, BufReader};
 use std::sync::{Arc, Mutex};
 use std::thread;
-use anyhow::{Result, Context};
+use anyhow::{anyhow, Context, Result};
+use std::cell::RefCell;
+use std::collections::HashSet;
 use std::path::{Path, PathBuf};
 
 mod modules;
-use modules::cli::{CliArgs, parse_args};
-use modules::knowledge_cache::KnowledgeCache;
+use modules::cli::{CliArgs, CliCommand, parse_args};
+use modules::cache_commands;
+use modules::knowledge_cache::{self, KnowledgeCache};
 use modules::llm_interface::LLMInterface;
+use modules::llm_backend::LlmConfig;
 use modules::web_agent::WebAgent;
-use modules::patch_engine::PatchEngine;
-use modules::issue_detector::{self, IssueClassification};
+use modules::patch_engine::{self, ErrorCheck, PatchEngine, TestCheck, WarningCheck};
+use modules::change_set::ChangeSet;
+use modules::approval::Interrupt;
+use modules::issue_detector::{self, issue_key, DetectedIssue, IssueClassification};
 use modules::cargo_expert::CargoExpert;
-use modules::project_analyzer::ProjectAnalyzer;
+use modules::linker::LinkerExpert;
+use modules::build_script::{self, BuildScriptExpert};
+use modules::project_analyzer::{self, ProjectAnalyzer};
+use modules::workspace::{self, ScratchCopy, Workspace};
+use modules::test_runner::{self, TestFailure};
 use modules::quick_fixes;
+use modules::rustc_suggestions;
+use modules::watcher;
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct CargoMessage {
     pub reason: String,
+    #[serde(default)]
+    pub package_id: Option<String>,
+    #[serde(default)]
+    pub target: Option<CargoTarget>,
     pub message: Option<CompilerMessage>,
 }
 
+#[derive(Debug, Deserialize, Clone)]
+pub struct CargoTarget {
+    pub name: String,
+    #[serde(default)]
+    pub kind: Vec<String>,
+}
+
+/// Единица компиляции (пакет + цель), в которой cargo получил диагностику.
+#[derive(Debug, Clone, Default)]
+pub struct CompilationUnit {
+    pub package_id: String,
+    pub target: String,
+    pub build_script: bool,
+    /// Порядок появления единицы в выводе cargo: зависимости собираются раньше зависимых.
+    pub order: usize,
+}
+
+impl CompilationUnit {
+    /// Каталог пакета из `package_id`: `path+file:///dir#0.1.0` или `name 0.1.0 (path+file:///dir)`.
+    pub fn package_dir(&self) -> Option<PathBuf> {
+        let start = self.package_id.find("file://")? + "file://".len();
+        let rest = &self.package_id[start..];
+        let end = rest.find(['#', ')']).unwrap_or(rest.len());
+        Some(PathBuf::from(&rest[..end]))
+    }
+}
+
+impl CargoMessage {
+    /// Диагностика компилятора с привязкой к единице компиляции (порядок проставляется позже).
+    pub fn into_compiler_message(self) -> Option<CompilerMessage> {
+        let mut message = self.message?;
+        if let (Some(package_id), Some(target)) = (self.package_id, self.target) {
+            message.unit = Some(CompilationUnit {
+                package_id,
+                build_script: target.kind.iter().any(|k| k == "custom-build"),
+                target: target.name,
+                order: 0,
+            });
+        }
+        Some(message)
+    }
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct CompilerMessage {
     pub message: String,
     pub level: String,
     pub code: Option<ErrorCode>,
     pub spans: Vec<Span>,
+    /// Дочерние help/note-диагностики: в них обычно и лежат подсказки по исправлению.
+    #[serde(default)]
+    pub children: Vec<CompilerMessage>,
+    /// Полный текст, как его печатает rustc (с фрагментом кода и стрелками).
+    #[serde(default)]
+    pub rendered: Option<String>,
+    /// Заполняется из обёртки cargo, в JSON самого rustc этого поля нет.
+    #[serde(skip)]
+    pub unit: Option<CompilationUnit>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct ErrorCode {
     pub code: String,
+    #[serde(default)]
+    pub explanation: Option<String>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Span {
     pub file_name: String,
+    #[serde(default)]
+    pub byte_start: usize,
+    #[serde(default)]
+    pub byte_end: usize,
     pub line_start: usize,
     #[serde(default)]
+    pub line_end: usize,
+    #[serde(default)]
+    pub column_start: usize,
+    #[serde(default)]
+    pub column_end: usize,
+    #[serde(default)]
+    pub is_primary: bool,
+    #[serde(default)]
+    pub text: Vec<SpanLine>,
+    #[serde(default)]
+    pub label: Option<String>,
+    #[serde(default)]
     pub suggested_replacement: Option<String>,
+    #[serde(default)]
+    pub suggestion_applicability: Option<Applicability>,
+    #[serde(default)]
+    pub expansion: Option<Box<SpanExpansion>>,
+}
+
+/// Строка исходника, на которую указывает span, и подсвеченный диапазон (1-based колонки).
+#[derive(Debug, Deserialize, Clone)]
+pub struct SpanLine {
+    pub text: String,
+    pub highlight_start: usize,
+    pub highlight_end: usize,
+}
+
+/// Откуда взялся код, если span лежит внутри раскрытого макроса.
+#[derive(Debug, Deserialize, Clone)]
+pub struct SpanExpansion {
+    pub span: Span,
+    pub macro_decl_name: String,
+    #[serde(default)]
+    pub def_site_span: Option<Span>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Applicability {
+    MachineApplicable,
+    MaybeIncorrect,
+    HasPlaceholders,
+    Unspecified,
+}
+
+impl CompilerMessage {
+    /// Основной span (is_primary); если rustc его не пометил — первый.
+    pub fn primary_span(&self) -> Option<&Span> {
+        self.spans.iter().find(|s| s.is_primary).or_else(|| self.spans.first())
+    }
+
+    /// Текст диагностики для промптов: вывод rustc целиком, включая help/note,
+    /// и первый абзац `rustc --explain` для кода ошибки.
+    pub fn prompt_text(&self) -> String {
+        let mut text = match &self.rendered {
+            Some(rendered) if !rendered.trim().is_empty() => rendered.trim_end().to_string(),
+            _ => format!("{}\n{}", self.message, patch_engine::stringify_spans(self)),
+        };
+        // rustc печатает файлы вне каталога запуска (path-зависимости) абсолютными путями
+        for file in self.absolute_files() {
+            text = text.replace(&file, &workspace::relative_to_cwd(Path::new(&file)));
+        }
+        if let Some((code, summary)) = self.explanation_summary() {
+            text.push_str(&format!("\nExplanation ({code}): {summary}"));
+        }
+        text
+    }
+
+    /// Абсолютные пути файлов из спанов самой диагностики и её дочерних подсказок.
+    fn absolute_files(&self) -> Vec<String> {
+        let mut files: Vec<String> = self
+            .spans
+            .iter()
+            .filter(|s| Path::new(&s.file_name).is_absolute())
+            .map(|s| s.file_name.clone())
+            .collect();
+        files.extend(self.children.iter().flat_map(CompilerMessage::absolute_files));
+        files
+    }
+
+    fn explanation_summary(&self) -> Option<(&str, &str)> {
+        let code = self.code.as_ref()?;
+        let summary = code.explanation.as_deref()?.split("\n\n").next()?.trim();
+        (!summary.is_empty()).then_some((code.code.as_str(), summary))
+    }
+
+    /// Сообщение, метки спанов и тексты всех дочерних диагностик — для поиска по ключевым словам.
+    pub fn full_text(&self) -> String {
+        let mut out = self.message.clone();
+        for label in self.spans.iter().filter_map(|s| s.label.as_deref()) {
+            out.push('\n');
+            out.push_str(label);
+        }
+        for child in &self.children {
+            out.push('\n');
+            out.push_str(&child.full_text());
+        }
+        out
+    }
+}
+
+/// Общие подсистемы агента, которые нужны и разовому прогону, и watch-режиму.
+pub struct Agent<'a> {
+    pub args: &'a CliArgs,
+    pub cache: &'a KnowledgeCache,
+    pub llm: &'a LLMInterface,
+    pub web: &'a WebAgent,
+    pub cargo_expert: &'a CargoExpert<'a>,
+    pub linker_expert: &'a LinkerExpert<'a>,
+    pub build_script_expert: &'a BuildScriptExpert<'a>,
+    pub analyzer: &'a ProjectAnalyzer,
+    pub changes: &'a ChangeSet,
+    /// Искать ли решения в интернете (выключено для фикстур LLM и по --no-web).
+    pub web_enabled: bool,
+    /// Пользоваться ли кэшем решений (выключено для фикстур LLM и по --no-cache):
+    /// попадание в кэш или примеры из него меняют промпты между запусками.
+    pub cache_enabled: bool,
+    /// Ошибки, которые пользователь пропустил в интерактивном режиме.
+    pub skipped: RefCell<HashSet<String>>,
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args: CliArgs = parse_args();
+    if let Some(CliCommand::Cache { action }) = &args.command {
+        return cache_commands::run(action, args.cache_scope);
+    }
     println!("{}", "🚀 RustyFixerAI v2.0.0".bold().yellow());
 
     let spinner = create_spinner("Preparing subsystems...");
-    let cache = KnowledgeCache::new().context("Failed to init knowledge cache")?;
-    let llm = LLMInterface::new()?;
+    let cache = KnowledgeCache::new(args.cache_scope).context("Failed to init knowledge cache")?;
+    let llm_config = LlmConfig {
+        backend: args.llm_backend.clone(),
+        url: args.llm_url.clone(),
+        model: args.llm_model.clone(),
+        api_key: args.llm_api_key.clone(),
+        timeout_secs: args.llm_timeout,
+        record: args.llm_record.clone(),
+        replay: args.llm_replay.clone(),
+    };
+    let llm = LLMInterface::new(&llm_config)?;
     let web = WebAgent::new();
-    let cargo_expert = CargoExpert::new(&llm);
-    let _analyzer = ProjectAnalyzer::new();
-    spinner.finish_with_message("Subsystems ready.");
+    let changes = ChangeSet::new(args.interactive);
+    let cargo_expert = CargoExpert::new(&llm, &changes);
+    let linker_expert = LinkerExpert::new(&changes);
+    let build_script_expert = BuildScriptExpert::new(&linker_expert);
+    let analyzer = ProjectAnalyzer::new();
+    spinner.finish_with_message(format!("Subsystems ready. LLM: {}", llm.describe()));
 
-    loop {
-        let (errors, warnings) = run_cargo_and_collect("build")
-            .context("Cargo build failed to execute")?;
-
-        if errors.is_empty() {
-            println!("{}", "✅ No errors found.".green().bold());
-            if args.fix_warnings && !warnings.is_empty() {
-                println!("{}", "⚠️ Fix-warnings pass enabled".yellow().bold());
-                // TODO: pass for warnings
+    let agent = Agent {
+        args: &args,
+        cache: &cache,
+        llm: &llm,
+        web: &web,
+        cargo_expert: &cargo_expert,
+        linker_expert: &linker_expert,
+        build_script_expert: &build_script_expert,
+        analyzer: &analyzer,
+        changes: &changes,
+        web_enabled: !args.no_web && !llm_config.uses_fixture(),
+        cache_enabled: !args.no_cache && !llm_config.uses_fixture(),
+        skipped: RefCell::new(HashSet::new()),
+    };
+
+    if args.watch {
+        return stop_on_quit(watcher::run(&agent).await);
+    }
+
+    if args.dry_run {
+        return run_dry(&agent).await;
+    }
+
+    stop_on_quit(agent.fix_until_clean().await)
+}
+
+/// Выход по команде пользователя — штатное завершение, а не ошибка.
+fn stop_on_quit(result: Result<()>) -> Result<()> {
+    match result {
+        Err(e) if e.downcast_ref::<Interrupt>() == Some(&Interrupt::Quit) => {
+            println!("{}", "Stopped by user.".yellow().bold());
+            Ok(())
+        }
+        other => other,
+    }
+}
+
+/// Dry-run: весь конвейер работает в теневой копии проекта,
+/// а проверенные правки выводятся одним патчем. Рабочее дерево не меняется.
+async fn run_dry(agent: &Agent<'_>) -> Result<()> {
+    let original_dir = std::env::current_dir()?;
+    let patch_out = agent.args.patch_out.as_ref().map(|p| original_dir.join(p));
+
+    let shadow = ScratchCopy::create(Path::new(".")).await
+        .context("Failed to copy the project into a scratch directory")?;
+    std::env::set_current_dir(shadow.path())?;
+    println!("{}", "🧪 Dry run: working on a scratch copy of the project.".cyan().bold());
+
+    let result = agent.fix_until_clean().await;
+    std::env::set_current_dir(&original_dir)?;
+    stop_on_quit(result)?;
+
+    if agent.changes.is_empty() {
+        println!("{}", "Dry run produced no changes.".yellow());
+        return Ok(());
+    }
+    let patch = agent.changes.unified_diff();
+    match patch_out {
+        Some(path) => {
+            std::fs::write(&path, &patch)
+                .with_context(|| format!("Failed to write patch to {}", path.display()))?;
+            println!("{} {}", "📝 Patch written to".green().bold(), path.display());
+        }
+        None => print!("{patch}"),
+    }
+    Ok(())
+}
+
+impl Agent<'_> {
+    /// Основной цикл: собираем проект, чиним первую ошибку, повторяем.
+    pub async fn fix_until_clean(&self) -> Result<()> {
+        loop {
+            let (errors, warnings) = run_cargo_and_collect(&["build"])
+                .context("Cargo build failed to execute")?;
+
+            if errors.is_empty() {
+                println!("{}", "✅ No errors found.".green().bold());
+                if self.args.fix_warnings && !warnings.is_empty() {
+                    println!("{}", "⚠️ Fix-warnings pass enabled".yellow().bold());
+                    self.fix_warnings(warnings, &[]).await?;
+                }
+                if self.args.clippy {
+                    println!("{}", "📎 Clippy pass enabled".yellow().bold());
+                    self.fix_clippy().await?;
+                }
+                if self.args.fix_tests {
+                    println!("{}", "🧪 Fix-tests pass enabled".yellow().bold());
+                    self.fix_tests().await?;
+                }
+                break;
+            }
+
+            let actionable: Vec<CompilerMessage> = errors
+                .iter()
+                .filter(|e| !self.skipped.borrow().contains(&issue_key(e)))
+                .cloned()
+                .collect();
+            let Some(issue) = issue_detector::prioritize_and_classify(&actionable) else {
+                println!("{}", "No actionable errors.".yellow());
+                break;
+            };
+
+            match self.handle_issue(&issue, &errors).await {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) if e.downcast_ref::<Interrupt>() == Some(&Interrupt::SkipIssue) => {
+                    println!("{}", "Issue skipped.".yellow());
+                    self.skipped.borrow_mut().insert(issue_key(&issue.message));
+                }
+                Err(e) => return Err(e),
             }
-            break;
         }
 
-        let Some(issue) = issue_detector::prioritize_and_classify(&errors) else {
-            println!("{}", "No actionable errors.".yellow());
-            break;
+        Ok(())
+    }
+
+    /// Проход по линтам clippy: сначала все машинно применимые подсказки одной правкой
+    /// (как `cargo clippy --fix`), затем оставшиеся линты по одному через обычный проход по предупреждениям.
+    async fn fix_clippy(&self) -> Result<()> {
+        let command = clippy_command(&self.args.clippy_lints, &self.args.clippy_allow);
+        let command_ref: Vec<&str> = command.iter().map(String::as_str).collect();
+        let (errors, warnings) = run_cargo_and_collect(&command_ref).context("cargo clippy failed to execute")?;
+        if !errors.is_empty() {
+            eprintln!("{}", "cargo clippy reports errors; fix them before the clippy pass.".red().bold());
+            return Ok(());
+        }
+
+        let candidates = issue_detector::filter_warnings(&warnings, &self.args.only_warnings, &self.args.skip_warnings);
+        if let Some((set, included)) = rustc_suggestions::build_combined_patch(&candidates, self.args.allow_maybe_incorrect) {
+            println!("\n{} {} lint(s) have machine-applicable suggestions", "Clippy:".bold(), included.len());
+            let target_file = set.files.keys().next().cloned().unwrap_or_default();
+            let targets: Vec<CompilerMessage> = included.iter().map(|&i| candidates[i].clone()).collect();
+            let check = WarningCheck::new(&targets, &warnings);
+            let patch_engine = PatchEngine::new(
+                self.llm,
+                self.cache,
+                self.changes,
+                "clippy::suggestions".to_string(),
+                "",
+                &target_file,
+                "",
+                !self.cache_enabled,
+            )
+            .with_check_command(&command)
+            .with_warning_check(check);
+            match patch_engine.try_suggested_fix(set).await {
+                Ok(_) => {}
+                Err(e) if e.downcast_ref::<Interrupt>() == Some(&Interrupt::SkipIssue) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        let (errors, warnings) = run_cargo_and_collect(&command_ref).context("cargo clippy failed to execute")?;
+        if !errors.is_empty() {
+            eprintln!("{}", "Errors appeared after applying clippy suggestions; stopping.".red().bold());
+            return Ok(());
+        }
+        self.fix_warnings(warnings, &command).await
+    }
+
+    /// Второй проход: по одному чиним предупреждения разрешённых категорий.
+    /// Каждая правка обязана убрать своё предупреждение и не добавить новых ошибок и предупреждений.
+    /// `command` — команда cargo, которой собраны предупреждения (пусто — `cargo build`).
+    async fn fix_warnings(&self, mut warnings: Vec<CompilerMessage>, command: &[String]) -> Result<()> {
+        let rebuild: Vec<&str> = match command {
+            [] => vec!["build"],
+            command => command.iter().map(String::as_str).collect(),
         };
+        // попытки помним по месту: одинаковые предупреждения (`unused_mut`) в разных строках —
+        // разные задачи, и неудача с одним не должна отменять остальные
+        let attempt_key = |w: &CompilerMessage| {
+            let at = w.primary_span().map(|s| format!("{}:{}", s.line_start, s.column_start)).unwrap_or_default();
+            format!("{}@{at}", issue_key(w))
+        };
+        let mut attempted: HashSet<String> = HashSet::new();
+        loop {
+            let candidates: Vec<CompilerMessage> = issue_detector::filter_warnings(
+                &warnings,
+                &self.args.only_warnings,
+                &self.args.skip_warnings,
+            )
+            .into_iter()
+            .filter(|w| !attempted.contains(&attempt_key(w)))
+            .collect();
+
+            let Some(issue) = issue_detector::prioritize_and_classify(&candidates) else {
+                println!("{}", "✅ No more warnings to fix.".green().bold());
+                return Ok(());
+            };
+            let Some(span) = issue.message.primary_span() else {
+                return Ok(());
+            };
+            attempted.insert(attempt_key(&issue.message));
+
+            println!("\n{} {}", "Selected warning:".bold(), issue.message.message);
+            display_issue_details(&issue.message);
 
+            let target_file = span.file_name.clone();
+            let error_text = issue.message.prompt_text();
+            let signature = knowledge_cache::error_signature(&issue.message);
+            let check = WarningCheck::new(std::slice::from_ref(&issue.message), &warnings);
+            let patch_engine = PatchEngine::new(
+                self.llm,
+                self.cache,
+                self.changes,
+                signature,
+                &error_text,
+                &target_file,
+                "",
+                !self.cache_enabled,
+            )
+            .with_focus_line(span.line_start)
+            .with_full_file(self.args.full_file)
+            .with_check_command(command)
+            .with_warning_check(check);
+
+            let suggested = match rustc_suggestions::build_patch(&issue.message, self.args.allow_maybe_incorrect) {
+                Some(set) => patch_engine.try_suggested_fix(set).await,
+                None => Ok(false),
+            };
+            let result = match suggested {
+                Ok(true) => Ok(()),
+                Ok(false) => patch_engine.run_and_self_correct().await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = result {
+                if e.downcast_ref::<Interrupt>() == Some(&Interrupt::Quit) {
+                    return Err(e);
+                }
+                eprintln!("{} {e:#}", "Failed to fix warning:".red().bold());
+            }
+
+            let (errors, fresh_warnings) = run_cargo_and_collect(&rebuild)
+                .context("Cargo build failed to execute")?;
+            if !errors.is_empty() {
+                eprintln!("{}", "Errors appeared during the warnings pass; stopping.".red().bold());
+                return Ok(());
+            }
+            warnings = fresh_warnings;
+        }
+    }
+
+    /// Проход по упавшим тестам: для каждого ищем тестируемый код и просим модель исправить его.
+    /// Правка принимается, только если тест прошёл, другие не сломались, а сам тест не тронут.
+    async fn fix_tests(&self) -> Result<()> {
+        let mut attempted: HashSet<String> = HashSet::new();
+        loop {
+            let run = test_runner::run_tests(Path::new(".")).context("cargo test failed to execute")?;
+            let Some(failure) = run.failures.iter().find(|f| !attempted.contains(&f.key())).cloned() else {
+                if run.failures.is_empty() {
+                    println!("{}", "✅ All tests pass.".green().bold());
+                } else {
+                    println!("{}", "No more failing tests to fix.".yellow());
+                }
+                return Ok(());
+            };
+            attempted.insert(failure.key());
+
+            println!("\n{} {}", "Failing test:".bold(), failure.name);
+            println!("  {}", failure.message.dimmed());
+
+            let test_file = failure
+                .location
+                .as_ref()
+                .map(|(file, _)| file.clone())
+                .or_else(|| failure.is_doc_test().then(|| failure.name.split(" - ").next().unwrap_or_default().to_string()));
+            let test_text = test_file.as_deref().and_then(|f| std::fs::read_to_string(f).ok());
+            let test_source = test_text
+                .as_deref()
+                .and_then(|text| project_analyzer::function_source(text, failure.function_name()));
+
+            // Тестируемый код: первая вызванная в тесте функция, определённая вне tests/
+            let callees = match (&test_source, failure.is_doc_test()) {
+                (_, true) => vec![failure.function_name().to_string()],
+                (Some(source), false) => project_analyzer::called_functions(source),
+                (None, false) => Vec::new(),
+            };
+            let mut target: Option<(String, Option<usize>)> = None;
+            for name in callees.iter().filter(|n| n.as_str() != failure.function_name()) {
+                let definitions = self.analyzer.definitions(name, Path::new("."))?;
+                let Some(definition) = definitions.into_iter().find(|d| !d.file.starts_with("tests/")) else {
+                    continue;
+                };
+                println!("  {} `{name}` in {}", "Code under test:".dimmed(), definition.file);
+                target = Some((definition.file, Some(definition.lines.0)));
+                break;
+            }
+            let Some((target_file, focus_line)) = target.or_else(|| {
+                let (file, line) = failure.location.clone()?;
+                (!file.starts_with("tests/")).then_some((file, Some(line)))
+            }) else {
+                println!("{}", "Could not locate the code under test; skipping.".yellow());
+                continue;
+            };
+
+            let mut error_text = failure.describe();
+            if let (Some(file), Some(source)) = (&test_file, &test_source) {
+                error_text.push_str(&format!("\n\n--- TEST CODE ({file}, must not be changed) ---\n{source}"));
+            }
+            let signature = format!("test::{}::{}", failure.key(), target_file);
+            let check = TestCheck {
+                target: failure.clone(),
+                baseline: run.failures.iter().map(TestFailure::key).collect(),
+                test_file,
+                test_source,
+            };
+            let mut patch_engine = PatchEngine::new(
+                self.llm,
+                self.cache,
+                self.changes,
+                signature,
+                &error_text,
+                &target_file,
+                "",
+                !self.cache_enabled,
+            )
+            .with_full_file(self.args.full_file)
+            .with_strategy_hint(TEST_STRATEGY_HINT)
+            .with_test_check(check);
+            if let Some(line) = focus_line {
+                patch_engine = patch_engine.with_focus_line(line);
+            }
+
+            if let Err(e) = patch_engine.run_and_self_correct().await {
+                match e.downcast_ref::<Interrupt>() {
+                    Some(Interrupt::Quit) => return Err(e),
+                    Some(Interrupt::SkipIssue) => println!("{}", "Test skipped.".yellow()),
+                    None => eprintln!("{} {e:#}", "Failed to fix test:".red().bold()),
+                }
+            }
+        }
+    }
+
+    /// Строит план поиска и собирает контекст из интернета; пусто, если поиск выключен.
+    async fn research(&self, error_message: &str) -> Result<String> {
+        if !self.web_enabled {
+            return Ok(String::new());
+        }
+        let plan = self.llm.analyze_error(error_message).await?;
+        Ok(self.web.investigate(&plan).await.unwrap_or_default())
+    }
+
+    /// Пытается исправить одну ошибку (с `--batch` — вместе с соседними).
+    /// `errors` — все текущие ошибки проекта: с ними сравнивается результат правки.
+    /// Возвращает Ok(false), если продолжать цикл исправлений нет смысла.
+    pub async fn handle_issue(&self, issue: &DetectedIssue, errors: &[CompilerMessage]) -> Result<bool> {
         println!("\n{} {}", "Selected issue:".bold(), issue.message.message);
         display_issue_details(&issue.message);
+        println!("  {} {:?}", "Class:".dimmed(), issue.classification);
 
         match issue.classification {
-            IssueClassification::CargoManifest => {
+            IssueClassification::MissingDependency | IssueClassification::MissingFeature => {
                 // 1) Файл, где всплыла ошибка
-                let Some(span) = issue.message.spans.first() else {
+                let Some(span) = issue.message.primary_span() else {
                     eprintln!("{}", "Compiler message has no spans; skipping.".red());
-                    break;
+                    return Ok(false);
                 };
                 let target_file = PathBuf::from(&span.file_name);
 
@@ -94,11 +629,12 @@ async fn main() -> Result<()> {
                     .context("Failed to find a package Cargo.toml for the affected file")?;
 
                 // 3) Пытаемся поправить Cargo.toml именно по этому пути
-                let manifest_applied = match cargo_expert
-                    .fix_manifest_issue_at(&issue.message, &manifest_rel)
+                let manifest_applied = match self.cargo_expert
+                    .fix_manifest_issue_at(&issue.message, &manifest_rel, issue.classification)
                     .await
                 {
                     Ok(applied) => applied,
+                    Err(e) if e.is::<Interrupt>() => return Err(e),
                     Err(e) => {
                         eprintln!("{} {e:#}", "Cargo manifest fix failed:".red().bold());
                         false
@@ -110,49 +646,243 @@ async fn main() -> Result<()> {
                     let msg = issue.message.message.to_lowercase();
                     let derives = msg.contains("derive macro `serialize`") || msg.contains("derive macro `deserialize`");
                     if derives {
-                        let _ = quick_fixes::ensure_serde_import(&span.file_name).await?;
+                        let _ = quick_fixes::ensure_serde_import(&span.file_name, &issue.message, errors, self.changes).await?;
                     }
                 }
+                Ok(true)
             }
-            IssueClassification::Code | IssueClassification::Unknown => {
-                let Some(span) = issue.message.spans.first() else {
+            IssueClassification::Import
+            | IssueClassification::TypeMismatch
+            | IssueClassification::BorrowCheck
+            | IssueClassification::TraitBound
+            | IssueClassification::MethodResolution
+            | IssueClassification::TypeInference
+            | IssueClassification::Macro
+            | IssueClassification::Lifetime
+            | IssueClassification::Code
+            | IssueClassification::Unknown => {
+                let Some(span) = issue.message.primary_span() else {
                     eprintln!("{}", "Compiler message has no spans; skipping.".red());
-                    break;
+                    return Ok(false);
                 };
                 let target_file = span.file_name.clone();
+                let batch = match self.args.batch {
+                    Some(scope) => {
+                        let pool: Vec<CompilerMessage> = errors
+                            .iter()
+                            .filter(|e| !self.skipped.borrow().contains(&issue_key(e)))
+                            .cloned()
+                            .collect();
+                        issue_detector::batch_for(issue, &pool, scope)
+                    }
+                    None => vec![issue.message.clone()],
+                };
+                let error_text = issue.message.prompt_text();
 
-                let plan = llm.analyze_error(&issue.message.message).await?;
-                let web_context = web.investigate(&plan).await.unwrap_or_default();
+                // 0) Готовые подсказки rustc: без LLM и без сети
+                if let Some(set) = rustc_suggestions::build_patch(&issue.message, self.args.allow_maybe_incorrect) {
+                    let engine = PatchEngine::new(
+                        self.llm,
+                        self.cache,
+                        self.changes,
+                        String::new(),
+                        &error_text,
+                        &target_file,
+                        "",
+                        true,
+                    )
+                    .with_error_check(ErrorCheck::new(std::slice::from_ref(&issue.message), errors));
+                    match engine.try_suggested_fix(set).await {
+                        Ok(true) => return Ok(true),
+                        Ok(false) => {}
+                        Err(e) if e.is::<Interrupt>() => return Err(e),
+                        Err(e) => eprintln!("{} {e:#}", "Applying rustc suggestion failed:".red().bold()),
+                    }
+                }
 
-                let signature = format!("{}::{}", issue.message.message, target_file);
+                let web_context = self.research(&error_text).await?;
+                // Типы и функции из других файлов, с которыми работает ошибка: без них модель гадает
+                let exclude = self.args.full_file.then_some(target_file.as_str());
+                let mut project_context = self
+                    .analyzer
+                    .related_items(&issue.message, Path::new("."), exclude)
+                    .await
+                    .unwrap_or_default();
+                if !project_context.is_empty() {
+                    println!("    -> Attaching related definitions from the project.");
+                }
+                // какие крейты доступны для `use` — из модели воркспейса
+                if let Ok(workspace) = Workspace::load(Path::new(".")) {
+                    if let Some(package) = workspace.package_for_file(Path::new(&target_file)) {
+                        project_context.insert_str(0, &format!("{}\n", workspace.describe_package(package)));
+                    }
+                }
+
+                let batch_text = if batch.len() > 1 {
+                    println!("    -> Fixing {} errors in {} with one edit.", batch.len(), target_file);
+                    batch_prompt_text(&batch)
+                } else {
+                    error_text.clone()
+                };
+
+                let signature = knowledge_cache::error_signature(&issue.message);
                 let patch_engine = PatchEngine::new(
-                    &llm,
-                    &cache,
+                    self.llm,
+                    self.cache,
+                    self.changes,
                     signature,
-                    &issue.message.message,
+                    &batch_text,
                     &target_file,
                     &web_context,
-                    args.no_cache,
-                );
+                    !self.cache_enabled,
+                )
+                .with_focus_line(span.line_start)
+                .with_full_file(self.args.full_file)
+                .with_strategy_hint(issue.classification.strategy_hint())
+                .with_project_context(&project_context)
+                .with_error_check(ErrorCheck::new(&batch, errors));
 
                 if let Err(e) = patch_engine.run_and_self_correct().await {
+                    if e.is::<Interrupt>() {
+                        return Err(e);
+                    }
                     eprintln!("{} {e:#}", "Failed to fix code:".red().bold());
-                    break;
+                    return Ok(false);
                 }
+                Ok(true)
             }
             IssueClassification::Linker => {
-                eprintln!("{}", "Linker issue type is not implemented yet.".yellow());
-                break;
+                let manifest_rel = unit_manifest(&issue.message)
+                    .context("Failed to find a package Cargo.toml for the linked crate")?;
+                match self.linker_expert.fix_linker_issue_at(&issue.message, &manifest_rel, errors).await {
+                    Ok(applied) => Ok(applied),
+                    Err(e) if e.is::<Interrupt>() => Err(e),
+                    Err(e) => {
+                        eprintln!("{} {e:#}", "Linker fix failed:".red().bold());
+                        Ok(false)
+                    }
+                }
+            }
+            IssueClassification::BuildScript => {
+                let manifest_rel = unit_manifest(&issue.message)
+                    .context("Failed to find a package Cargo.toml for the build script")?;
+                match self.build_script_expert.fix_build_script_at(&issue.message, &manifest_rel, errors).await {
+                    Ok(true) => return Ok(true),
+                    Ok(false) => {}
+                    Err(e) if e.is::<Interrupt>() => return Err(e),
+                    Err(e) => eprintln!("{} {e:#}", "Build script fix failed:".red().bold()),
+                }
+
+                // Готового рецепта нет — просим модель поправить сам build.rs
+                let manifest = std::fs::read_to_string(&manifest_rel)
+                    .with_context(|| format!("Failed to read {manifest_rel}"))?;
+                let Some(script) = build_script::script_path(&manifest_rel, &manifest)
+                    .filter(|p| Path::new(p).exists())
+                else {
+                    return Ok(false);
+                };
+                let error_text = issue.message.prompt_text();
+                let web_context = self.research(&error_text).await?;
+                let signature = knowledge_cache::error_signature(&issue.message);
+                let mut patch_engine = PatchEngine::new(
+                    self.llm,
+                    self.cache,
+                    self.changes,
+                    signature,
+                    &error_text,
+                    &script,
+                    &web_context,
+                    !self.cache_enabled,
+                )
+                .with_full_file(self.args.full_file)
+                .with_strategy_hint(issue.classification.strategy_hint())
+                .with_error_check(ErrorCheck::new(std::slice::from_ref(&issue.message), errors));
+                if let Some(line) = build_script::panic_line(&error_text) {
+                    patch_engine = patch_engine.with_focus_line(line);
+                }
+
+                if let Err(e) = patch_engine.run_and_self_correct().await {
+                    if e.is::<Interrupt>() {
+                        return Err(e);
+                    }
+                    eprintln!("{} {e:#}", "Failed to fix build script:".red().bold());
+                    return Ok(false);
+                }
+                Ok(true)
             }
         }
     }
+}
 
-    Ok(())
+/// `cargo clippy` с выбранными группами линтов. `--cap-lints warn` превращает `#![deny(...)]`
+/// и `-D warnings` из конфигурации проекта в предупреждения, чтобы их чинил проход по предупреждениям.
+fn clippy_command(enable: &[String], allow: &[String]) -> Vec<String> {
+    let lint = |name: &String| {
+        if name.starts_with("clippy::") { name.clone() } else { format!("clippy::{name}") }
+    };
+    let mut command: Vec<String> = ["clippy", "--", "--cap-lints", "warn"].map(String::from).into();
+    for name in enable {
+        command.extend(["-W".to_string(), lint(name)]);
+    }
+    for name in allow {
+        command.extend(["-A".to_string(), lint(name)]);
+    }
+    command
+}
+
+const TEST_STRATEGY_HINT: &str = "A test fails at runtime. The test is the specification: fix the \
+    implementation it calls so the assertion holds. Do not modify the test, its assertions or files in tests/.";
+
+/// Текст пакета ошибок для модели: все ошибки подряд, с просьбой исправить их одной правкой.
+fn batch_prompt_text(batch: &[CompilerMessage]) -> String {
+    let mut out = format!("There are {} errors to fix together with one set of edits.\n", batch.len());
+    for (n, err) in batch.iter().enumerate() {
+        out.push_str(&format!("\n--- ERROR {} of {} ---\n{}\n", n + 1, batch.len(), err.prompt_text()));
+    }
+    out
+}
+
+/// Нумерует единицы компиляции в порядке их первого появления в выводе cargo.
+fn assign_unit_order(messages: &mut [CompilerMessage]) {
+    let mut seen: Vec<(String, String)> = Vec::new();
+    for unit in messages.iter_mut().filter_map(|m| m.unit.as_mut()) {
+        let id = (unit.package_id.clone(), unit.target.clone());
+        unit.order = match seen.iter().position(|s| *s == id) {
+            Some(n) => n,
+            None => {
+                seen.push(id);
+                seen.len() - 1
+            }
+        };
+    }
+}
+
+pub fn run_cargo_and_collect(command: &[&str]) -> Result<(Vec<CompilerMessage>, Vec<CompilerMessage>)> {
+    let mut all = run_cargo_in(Path::new("."), command)?;
+    let mut errors: Vec<_> = all.iter().filter(|m| m.level == "error").cloned().collect();
+    let mut warnings: Vec<_> = all.drain(..).filter(|m| m.level == "warning").collect();
+
+    // Окончательный порядок ошибок выбирает issue_detector; здесь — только стабильная сортировка по месту
+    let sort_key = |m: &CompilerMessage| {
+        let order = m.unit.as_ref().map_or(usize::MAX, |u| u.order);
+        let (file, line) = m.primary_span().map_or((String::new(), usize::MAX), |s| (s.file_name.clone(), s.line_start));
+        (order, file, line)
+    };
+    errors.sort_by_key(sort_key);
+    warnings.sort_by_key(sort_key);
+
+    Ok((errors, warnings))
 }
 
-fn run_cargo_and_collect(cmd: &str) -> Result<(Vec<CompilerMessage>, Vec<CompilerMessage>)> {
+/// Запускает cargo в каталоге `dir` и собирает все compiler-message с привязкой к единицам
+/// компиляции, плюс сбои build-скриптов из текстового stderr.
+/// `command` — подкоманда и её аргументы: `["check"]`, `["clippy", "--", "-W", "clippy::pedantic"]`.
+pub fn run_cargo_in(dir: &Path, command: &[&str]) -> Result<Vec<CompilerMessage>> {
+    let (cmd, rest) = command.split_first().context("Empty cargo command")?;
     let mut child = Command::new("cargo")
+        .current_dir(dir)
         .args([cmd, "--message-format=json"])
+        .args(rest)
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
         .spawn()
@@ -167,10 +897,10 @@ fn run_cargo_and_collect(cmd: &str) -> Result<(Vec<CompilerMessage>, Vec<Compile
     if let Some(stdout) = child.stdout.take() {
         threads.push(thread::spawn(move || {
             let reader = BufReader::new(stdout);
-            for line in reader.lines().flatten() {
+            for line in reader.lines().map_while(Result::ok) {
                 if let Ok(msg) = serde_json::from_str::<CargoMessage>(&line) {
                     if msg.reason == "compiler-message" {
-                        if let Some(compiler_msg) = msg.message {
+                        if let Some(compiler_msg) = msg.into_compiler_message() {
                             messages_out.lock().unwrap().push(compiler_msg);
                         }
                     }
@@ -182,35 +912,35 @@ fn run_cargo_and_collect(cmd: &str) -> Result<(Vec<CompilerMessage>, Vec<Compile
     if let Some(stderr) = child.stderr.take() {
         threads.push(thread::spawn(move || {
             let reader = BufReader::new(stderr);
-            for line in reader.lines().flatten() {
-                if let Ok(msg) = serde_json::from_str::<CargoMessage>(&line) {
-                    if msg.reason == "compiler-message" {
-                        if let Some(compiler_msg) = msg.message {
+            let mut text = String::new();
+            for line in reader.lines().map_while(Result::ok) {
+                match serde_json::from_str::<CargoMessage>(&line) {
+                    Ok(msg) if msg.reason == "compiler-message" => {
+                        if let Some(compiler_msg) = msg.into_compiler_message() {
                             messages_err.lock().unwrap().push(compiler_msg);
                         }
                     }
+                    Ok(_) => {}
+                    Err(_) => {
+                        text.push_str(&line);
+                        text.push('\n');
+                    }
                 }
             }
+            // упавшие build-скрипты не дают compiler-message: cargo пишет их вывод в stderr текстом
+            messages_err.lock().unwrap().extend(build_script::parse_failures(&text));
         }));
     }
 
+    // Ненулевой код выхода — нормальная ситуация при ошибках компиляции; его не проверяем
     for t in threads {
-        t.join().unwrap();
-    }
-    let status = child.wait()?;
-    if !status.success() {
-        // Нормальная ситуация при ошибках компиляции; продолжаем парсить
+        t.join().map_err(|_| anyhow!("Reader thread for cargo {cmd} panicked"))?;
     }
+    child.wait()?;
 
-    let mut all = Arc::try_unwrap(messages).unwrap().into_inner().unwrap();
-    let mut errors: Vec<_> = all.iter().cloned().filter(|m| m.level == "error").collect();
-    let mut warnings: Vec<_> = all.drain(..).filter(|m| m.level == "warning").collect();
-
-    let sort_key = |m: &CompilerMessage| m.spans.first().map_or(usize::MAX, |s| s.line_start);
-    errors.sort_by_key(sort_key);
-    warnings.sort_by_key(sort_key);
-
-    Ok((errors, warnings))
+    let mut all = std::mem::take(&mut *messages.lock().map_err(|_| anyhow!("cargo {cmd} output is poisoned"))?);
+    assign_unit_order(&mut all);
+    Ok(all)
 }
 
 fn display_issue_details(issue: &CompilerMessage) {
@@ -224,9 +954,15 @@ fn display_issue_details(issue: &CompilerMessage) {
     if let Some(code) = &issue.code {
         println!("- {}: {}", "Code".bold(), code.code);
     }
-    if let Some(span) = issue.spans.first() {
+    if let Some(span) = issue.primary_span() {
         println!("- {}: {}", "File".bold(), span.file_name);
-        println!("- {}: {}", "Line".bold(), span.line_start);
+        println!("- {}: {}:{}", "Line".bold(), span.line_start, span.column_start);
+        if let Some(label) = &span.label {
+            println!("- {}: {}", "Label".bold(), label);
+        }
+    }
+    for child in &issue.children {
+        println!("- {}: {}", child.level.bold(), child.message);
     }
 }
 
@@ -242,9 +978,42 @@ fn create_spinner(msg: &str) -> ProgressBar {
     spinner
 }
 
-/// Ищет ближайший *пакетный* Cargo.toml, поднимаясь от файла вверх.
-/// Пропускает «виртуальные» манифесты, где только [workspace].
+/// Манифест пакета, в котором возникла диагностика без спанов (линковка, build.rs):
+/// по `package_id` из вывода cargo через модель воркспейса, иначе по пути из `package_id`,
+/// иначе корневой Cargo.toml.
+fn unit_manifest(message: &CompilerMessage) -> Result<String> {
+    let cwd = std::env::current_dir()?;
+    let Some(unit) = message.unit.as_ref() else {
+        return Ok("Cargo.toml".to_string());
+    };
+    if let Some(manifest) = Workspace::load(Path::new(".")).ok().and_then(|ws| {
+        let package = ws.package_by_id(&unit.package_id)?;
+        Some(package.manifest_path.clone())
+    }) {
+        let cwd = std::fs::canonicalize(&cwd).unwrap_or(cwd);
+        return Ok(manifest.strip_prefix(&cwd).unwrap_or(&manifest).to_string_lossy().to_string());
+    }
+    let Some(dir) = unit.package_dir() else {
+        return Ok("Cargo.toml".to_string());
+    };
+    let manifest = dir.join("Cargo.toml");
+    match manifest.strip_prefix(&cwd) {
+        Ok(rel) => Ok(rel.to_string_lossy().to_string()),
+        Err(_) => Ok(manifest.to_string_lossy().to_string()),
+    }
+}
+
+/// Манифест пакета, которому принадлежит файл, — по модели воркспейса из `cargo metadata`.
+/// Если cargo metadata не работает (часто — из-за сломанного манифеста, который мы и чиним),
+/// поднимаемся от файла вверх до первого Cargo.toml с секцией [package].
 fn find_nearest_package_manifest(start_file: &Path) -> Result<String> {
+    if let Some(manifest) = Workspace::load(Path::new(".")).ok().and_then(|ws| ws.manifest_for_file(start_file)) {
+        return Ok(manifest);
+    }
+
+    let is_package = |content: &str| {
+        content.parse::<toml_edit::DocumentMut>().is_ok_and(|doc| doc.get("package").is_some())
+    };
     let mut dir = start_file
         .parent()
         .ok_or_else(|| anyhow::anyhow!("No parent dir for file {}", start_file.display()))?;
@@ -254,8 +1023,7 @@ fn find_nearest_package_manifest(start_file: &Path) -> Result<String> {
         let candidate = dir.join("Cargo.toml");
         if candidate.exists() {
             let content = std::fs::read_to_string(&candidate)?;
-            let is_package = content.contains("[package]");
-            if is_package {
+            if is_package(&content) {
                 // отдаём относительный путь (от текущего каталога)
                 if let Ok(rel) = candidate.strip_prefix(&cwd) {
                     return Ok(rel.to_string_lossy().to_string());
@@ -274,7 +1042,7 @@ fn find_nearest_package_manifest(start_file: &Path) -> Result<String> {
     let root = cwd.join("Cargo.toml");
     if root.exists() {
         let content = std::fs::read_to_string(&root)?;
-        if content.contains("[package]") {
+        if is_package(&content) {
             return Ok("Cargo.toml".to_string());
         }
     }
@@ -0,0 +1,121 @@
+use super::change_set::PatchSet;
+use anyhow::{Context, Result};
+use colored::*;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::process::Command;
+
+/// Решение пользователя по предложенной правке.
+pub enum Review {
+    /// Применить предложенный набор правок.
+    Accept(PatchSet),
+    /// Применить набор, отредактированный пользователем: прежняя проверка к нему
+    /// уже не относится.
+    Edited(PatchSet),
+    /// Отклонить; строка — пояснение, которое уйдёт в LLM как негативный контекст.
+    Reject(String),
+}
+
+/// Прерывание, которое пользователь запросил из интерактивного диалога.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interrupt {
+    SkipIssue,
+    Quit,
+}
+
+impl fmt::Display for Interrupt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Interrupt::SkipIssue => write!(f, "issue skipped by user"),
+            Interrupt::Quit => write!(f, "stopped by user"),
+        }
+    }
+}
+
+impl std::error::Error for Interrupt {}
+
+/// Показывает цветной diff и спрашивает accept / reject / edit / skip / quit.
+pub fn ask(mut set: PatchSet) -> Result<Review> {
+    let files: Vec<&str> = set.files.keys().map(String::as_str).collect();
+    println!("\n{} {}", "Proposed change to".bold(), files.join(", ").bold());
+    print_colored_diff(&set.unified_diff());
+
+    let stdin = io::stdin();
+    loop {
+        print!("{} ", "[a]ccept / [r]eject / [e]dit / [s]kip issue / [q]uit >".bold().cyan());
+        io::stdout().flush().ok();
+
+        let mut answer = String::new();
+        if stdin.lock().read_line(&mut answer)? == 0 {
+            // stdin закрыт — безопаснее остановиться, чем молча применять
+            return Err(Interrupt::Quit.into());
+        }
+        match answer.trim().to_lowercase().as_str() {
+            "a" | "accept" | "y" | "yes" => return Ok(Review::Accept(set)),
+            "r" | "reject" | "n" | "no" => {
+                print!("{} ", "Reason (optional, sent to the model) >".dimmed());
+                io::stdout().flush().ok();
+                let mut reason = String::new();
+                stdin.lock().read_line(&mut reason)?;
+                return Ok(Review::Reject(reason.trim().to_string()));
+            }
+            "e" | "edit" => {
+                // файлы набора открываются в редакторе по очереди
+                for (path, edit) in set.files.iter_mut() {
+                    edit.after = edit_in_editor(path, &edit.after)?;
+                }
+                println!("{}", "Using the edited version.".green());
+                return Ok(Review::Edited(set));
+            }
+            "s" | "skip" => return Err(Interrupt::SkipIssue.into()),
+            "q" | "quit" => return Err(Interrupt::Quit.into()),
+            _ => println!("{}", "Please answer a, r, e, s or q.".yellow()),
+        }
+    }
+}
+
+pub fn print_colored_diff(patch: &str) {
+    for line in patch.lines() {
+        if line.starts_with("+++") || line.starts_with("---") {
+            println!("{}", line.bold());
+        } else if line.starts_with("@@") {
+            println!("{}", line.cyan());
+        } else if line.starts_with('+') {
+            println!("{}", line.green());
+        } else if line.starts_with('-') {
+            println!("{}", line.red());
+        } else {
+            println!("{}", line);
+        }
+    }
+}
+
+/// Открывает предложенное содержимое в $VISUAL / $EDITOR и возвращает результат.
+fn edit_in_editor(path: &str, content: &str) -> Result<String> {
+    let ext = std::path::Path::new(path)
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    let file = tempfile::Builder::new()
+        .prefix("rusty-fixer-")
+        .suffix(&ext)
+        .tempfile()
+        .context("Failed to create a temp file for editing")?;
+    std::fs::write(file.path(), content)?;
+
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| if cfg!(windows) { "notepad".into() } else { "vi".into() });
+    // $EDITOR может содержать аргументы, например `code --wait`
+    let mut parts = editor.split_whitespace();
+    let program = parts.next().unwrap_or("vi");
+    let status = Command::new(program)
+        .args(parts)
+        .arg(file.path())
+        .status()
+        .with_context(|| format!("Failed to launch editor `{editor}`"))?;
+    if !status.success() {
+        anyhow::bail!("Editor `{editor}` exited with {status}");
+    }
+    Ok(std::fs::read_to_string(file.path())?)
+}
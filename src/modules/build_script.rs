@@ -0,0 +1,260 @@
+use crate::{CompilationUnit, CompilerMessage};
+use super::change_set::PatchSet;
+use super::linker::{self, LinkerExpert, LinkerProblem};
+use anyhow::{Context, Result};
+use std::path::Path;
+use toml_edit::{DocumentMut, Item, Value};
+
+const FAILURE_PREFIX: &str = "error: failed to run custom build command for `";
+
+/// Сбой build-скрипта cargo пишет в stderr обычным текстом даже с `--message-format=json`.
+/// Превращает каждый такой блок в диагностику уровня error: `message` — без пути к пакету
+/// (он меняется при проверке во временной копии), `rendered` — вывод скрипта целиком.
+pub fn parse_failures(stderr: &str) -> Vec<CompilerMessage> {
+    let mut failures = Vec::new();
+    let mut lines = stderr.lines().peekable();
+    while let Some(line) = lines.next() {
+        let Some(rest) = line.strip_prefix(FAILURE_PREFIX) else {
+            continue;
+        };
+        let header = rest.split('`').next().unwrap_or(rest);
+
+        let mut rendered = vec![line.to_string()];
+        let mut in_backtrace = false;
+        while let Some(next) = lines.peek() {
+            // следующий блок cargo начинается с новой строки `error:`/`warning:` без отступа
+            if next.starts_with("error") || next.starts_with("warning") {
+                break;
+            }
+            let next = lines.next().unwrap_or_default();
+            let trimmed = next.trim();
+            if trimmed == "stack backtrace:" {
+                in_backtrace = true;
+                continue;
+            }
+            if in_backtrace {
+                in_backtrace = !trimmed.starts_with("note:");
+                continue;
+            }
+            rendered.push(next.to_string());
+        }
+
+        let (package, location) = match header.split_once(" (") {
+            Some((package, path)) => (package, Some(path.trim_end_matches(')'))),
+            None => (header, None),
+        };
+        let package_id = match (location, package.split_once(" v")) {
+            (Some(path), Some((_, version))) => format!("path+file://{path}#{version}"),
+            _ => package.replace(" v", "@"),
+        };
+        failures.push(CompilerMessage {
+            message: format!("failed to run custom build command for `{package}`"),
+            level: "error".to_string(),
+            code: None,
+            spans: Vec::new(),
+            children: Vec::new(),
+            rendered: Some(rendered.join("\n").trim_end().to_string()),
+            unit: Some(CompilationUnit {
+                package_id,
+                target: "build-script-build".to_string(),
+                build_script: true,
+                order: 0,
+            }),
+        });
+    }
+    failures
+}
+
+/// Путь к build.rs пакета (с учётом `build = "..."`); None, если build-скрипт отключён.
+pub fn script_path(manifest_rel_path: &str, manifest: &str) -> Option<String> {
+    let dir = Path::new(manifest_rel_path).parent().unwrap_or(Path::new(""));
+    let doc = manifest.parse::<DocumentMut>().ok()?;
+    let file = match doc.get("package").and_then(|p| p.get("build")) {
+        Some(item) if item.as_bool() == Some(false) => return None,
+        Some(item) => item.as_str()?.to_string(),
+        None => "build.rs".to_string(),
+    };
+    Some(dir.join(file).to_string_lossy().replace('\\', "/"))
+}
+
+/// Строка build.rs, на которой скрипт запаниковал: `panicked at build.rs:3:42:`.
+pub fn panic_line(output: &str) -> Option<usize> {
+    output.lines().find_map(|line| {
+        let location = line.split("panicked at ").nth(1)?;
+        let mut parts = location.trim_end_matches(':').split(':');
+        let file = parts.next()?;
+        file.ends_with("build.rs").then(|| parts.next()?.parse().ok()).flatten()
+    })
+}
+
+/// Распознанная причина сбоя build-скрипта.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Cause {
+    /// prost-build и др. не нашли компилятор `protoc`.
+    MissingProtoc,
+    MissingEnvVar(String),
+    MissingHeader(String),
+    /// `is `cmake` not installed?` и подобные сообщения крейтов `cc`/`cmake`.
+    MissingTool(String),
+    /// Проблема нативной библиотеки (pkg-config, `-l`) — её решает LinkerExpert.
+    Native(LinkerProblem),
+}
+
+fn diagnose(output: &str, script: Option<&str>) -> Vec<Cause> {
+    let mut causes = Vec::new();
+    let lower = output.to_lowercase();
+    if lower.contains("could not find `protoc`") || lower.contains("protoc failed") || lower.contains("`protoc` not found") {
+        causes.push(Cause::MissingProtoc);
+    }
+
+    for line in output.lines().map(str::trim) {
+        let header = line
+            .split("fatal error: ")
+            .nth(1)
+            .and_then(|r| r.split(':').next())
+            .or_else(|| {
+                line.ends_with("file not found")
+                    .then(|| line.split('\'').nth(1))
+                    .flatten()
+            });
+        if let Some(header) = header {
+            causes.push(Cause::MissingHeader(header.trim().to_string()));
+        }
+        if let Some(tool) = line.split("is `").nth(1).filter(|_| line.contains("not installed")) {
+            causes.push(Cause::MissingTool(tool.split('`').next().unwrap_or(tool).to_string()));
+        }
+    }
+
+    // `env::var("X").unwrap()` — имя переменной ищем в строке build.rs, где случилась паника
+    if output.contains("NotPresent") || lower.contains("environment variable not found") {
+        let name = script
+            .zip(panic_line(output))
+            .and_then(|(source, line)| source.lines().nth(line.saturating_sub(1)))
+            .and_then(|code| code.split("var(\"").nth(1))
+            .and_then(|rest| rest.split('"').next())
+            .unwrap_or("<unknown>");
+        causes.push(Cause::MissingEnvVar(name.to_string()));
+    }
+
+    causes.extend(linker::diagnose(output).into_iter().map(Cause::Native));
+    causes.dedup();
+    causes
+}
+
+pub struct BuildScriptExpert<'a> {
+    linker: &'a LinkerExpert<'a>,
+}
+
+impl<'a> BuildScriptExpert<'a> {
+    pub fn new(linker: &'a LinkerExpert<'a>) -> Self {
+        Self { linker }
+    }
+
+    /// Разбирает вывод упавшего build-скрипта пакета с манифестом `manifest_rel_path`
+    /// и пробует исправления в манифесте (build-dependencies, фичи `*-sys` крейтов) и build.rs.
+    /// Ok(false) — готового исправления нет, советы напечатаны; дальше можно звать модель.
+    pub async fn fix_build_script_at(
+        &self,
+        issue: &CompilerMessage,
+        manifest_rel_path: &str,
+        baseline: &[CompilerMessage],
+    ) -> Result<bool> {
+        println!("    -> Detected a build script failure. Engaging Build Script Expert.");
+        let output = issue.rendered.as_deref().unwrap_or(&issue.message);
+        let manifest = tokio::fs::read_to_string(manifest_rel_path)
+            .await
+            .with_context(|| format!("Failed to read {manifest_rel_path}"))?;
+        let script = script_path(manifest_rel_path, &manifest);
+        let source = match &script {
+            Some(path) => tokio::fs::read_to_string(path).await.ok(),
+            None => None,
+        };
+
+        let causes = diagnose(output, source.as_deref());
+        if causes.is_empty() {
+            println!("    -> Could not recognise the build script output.");
+            return Ok(false);
+        }
+
+        let mut advice = Vec::new();
+        let mut native = Vec::new();
+        for cause in causes {
+            match cause {
+                Cause::MissingProtoc => {
+                    println!("    -> Build script: `protoc` was not found");
+                    if let (Some(path), Some(source)) = (&script, &source) {
+                        if let Some(set) = protoc_patch(manifest_rel_path, &manifest, path, source) {
+                            println!("    -> Trying: vendored protoc from the `protoc-bin-vendored` build-dependency");
+                            if self.linker.verify_and_apply(set, issue, baseline).await? {
+                                return Ok(true);
+                            }
+                        }
+                    }
+                    advice.push(
+                        "Install the Protocol Buffers compiler: protobuf-compiler (Debian/Ubuntu), \
+                         protobuf-compiler (Fedora), protobuf (Homebrew), or set PROTOC."
+                            .to_string(),
+                    );
+                }
+                Cause::MissingEnvVar(name) => {
+                    println!("    -> Build script: environment variable `{name}` is not set");
+                    advice.push(format!(
+                        "Set `{name}` before building, or add it to the [env] section of .cargo/config.toml."
+                    ));
+                }
+                Cause::MissingHeader(header) => {
+                    println!("    -> Build script: C header `{header}` was not found");
+                    match linker::library_for_header(&header) {
+                        Some(lib) => native.push(LinkerProblem::MissingLibrary(lib.to_string())),
+                        None => advice.push(format!(
+                            "Install the development package that provides `{header}`."
+                        )),
+                    }
+                }
+                Cause::MissingTool(tool) => {
+                    println!("    -> Build script: `{tool}` is not installed");
+                    advice.push(format!("Install `{tool}` and make sure it is on PATH."));
+                }
+                Cause::Native(problem) => native.push(problem),
+            }
+        }
+
+        // LinkerExpert пробует фичи `vendored`/`bundled` и печатает итоговые советы
+        if native.is_empty() {
+            println!("    -> No automatic fix worked. Suggestions:");
+            for text in advice {
+                println!("       * {text}");
+            }
+            return Ok(false);
+        }
+        self.linker.try_fixes(&native, issue, manifest_rel_path, baseline, advice).await
+    }
+}
+
+/// `protoc-bin-vendored` в [build-dependencies] и `PROTOC` из него в начале `fn main` build.rs.
+fn protoc_patch(manifest_rel_path: &str, manifest: &str, script: &str, source: &str) -> Option<PatchSet> {
+    if source.contains("protoc_bin_vendored") {
+        return None;
+    }
+    let mut doc = manifest.parse::<DocumentMut>().ok()?;
+    if doc.get("build-dependencies").is_none() {
+        doc["build-dependencies"] = toml_edit::table();
+    }
+    doc["build-dependencies"]
+        .as_table_mut()?
+        .insert("protoc-bin-vendored", Item::Value(Value::from("3")));
+
+    // с edition 2024 `set_var` — unsafe
+    let edition = doc.get("package").and_then(|p| p.get("edition")).and_then(Item::as_str).unwrap_or("2015");
+    let set_var = "std::env::set_var(\"PROTOC\", protoc_bin_vendored::protoc_bin_path().unwrap());";
+    let line = if edition >= "2024" {
+        format!("    unsafe {{ {set_var} }}")
+    } else {
+        format!("    {set_var}")
+    };
+    let script_after = linker::insert_into_main(source, &line)?;
+
+    let mut set = PatchSet::single(manifest_rel_path, Some(manifest.to_string()), doc.to_string());
+    set.insert(script, Some(source.to_string()), script_after);
+    Some(set)
+}
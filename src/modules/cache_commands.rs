@@ -0,0 +1,260 @@
+use super::approval::print_colored_diff;
+use super::change_set::unified_diff;
+use super::cli::{CacheAction, CacheScope, CacheSort};
+use super::knowledge_cache::{CachedFix, EntryRef, KnowledgeCache};
+use super::span_patch;
+use anyhow::{bail, Context, Result};
+use colored::*;
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+
+const DAY_SECS: i64 = 24 * 60 * 60;
+
+/// Сколько символов сигнатуры помещается в строку списка.
+const SIGNATURE_WIDTH: usize = 80;
+
+/// Команды `rusty-fixer-ai cache ...`: просмотр и чистка кэша решений.
+pub fn run(action: &CacheAction, scope: CacheScope) -> Result<()> {
+    let cache = KnowledgeCache::new(scope).context("Failed to open knowledge cache")?;
+    match action {
+        CacheAction::List { sort, limit } => list(&cache, *sort, *limit),
+        CacheAction::Show { id } => show(&cache, *id),
+        CacheAction::Stats => stats(&cache),
+        CacheAction::Delete { ids } => {
+            let removed = cache.delete(ids)?;
+            println!("Deleted {removed} of {} entries.", ids.len());
+            Ok(())
+        }
+        CacheAction::Prune { older_than, min_success_rate, min_attempts, dry_run } => {
+            prune(&cache, *older_than, *min_success_rate, *min_attempts, *dry_run)
+        }
+        CacheAction::Vacuum => {
+            let before = db_size(&cache);
+            cache.vacuum()?;
+            println!("Database compacted: {} -> {}.", format_size(before), format_size(db_size(&cache)));
+            Ok(())
+        }
+        CacheAction::Export { file, min_success_rate } => export(&cache, file.as_deref(), *min_success_rate),
+        CacheAction::Import { file, on_conflict } => {
+            let fixes = read_jsonl(file)?;
+            let stats = cache.import(&fixes, *on_conflict)?;
+            println!(
+                "Imported {} entries: {} added, {} replaced, {} kept.",
+                fixes.len(),
+                stats.added,
+                stats.replaced,
+                stats.kept
+            );
+            Ok(())
+        }
+    }
+}
+
+fn list(cache: &KnowledgeCache, sort: CacheSort, limit: Option<usize>) -> Result<()> {
+    let mut entries = cache.entries()?;
+    if entries.is_empty() {
+        println!("{}", "The knowledge cache is empty.".yellow());
+        return Ok(());
+    }
+    match sort {
+        CacheSort::Recent => entries.sort_by_key(|e| -e.created_at),
+        CacheSort::Hits => entries.sort_by_key(|e| std::cmp::Reverse(e.hits)),
+        CacheSort::Failures => entries.sort_by(|a, b| a.success_rate().total_cmp(&b.success_rate())),
+    }
+
+    println!("{}", format!("{:>10}  {:>4}  {:>4}  {:>4}  {:>5}  {:>8}  SIGNATURE", "ID", "HITS", "OK", "FAIL", "RATE", "AGE").bold());
+    for entry in entries.iter().take(limit.unwrap_or(usize::MAX)) {
+        println!(
+            "{:>10}  {:>4}  {:>4}  {:>4}  {:>4.0}%  {:>8}  {}",
+            cache.label(entry),
+            entry.hits,
+            entry.successes,
+            entry.failures,
+            entry.success_rate() * 100.0,
+            format_age(entry.age_secs()),
+            truncate(&entry.signature, SIGNATURE_WIDTH)
+        );
+    }
+    Ok(())
+}
+
+fn show(cache: &KnowledgeCache, id: EntryRef) -> Result<()> {
+    let Some(entry) = cache.entry(id)? else {
+        bail!("No cache entry with id {}.", id.id);
+    };
+    println!("{} {}", "Signature:".bold(), entry.signature);
+    println!("{} {} ago", "Stored:".bold(), format_age(entry.age_secs()));
+    println!("{} {}", "Model:".bold(), or_unknown(&entry.model));
+    println!("{} {}", "Toolchain:".bold(), or_unknown(&entry.toolchain));
+    println!("{} {}", "Crates:".bold(), or_unknown(&entry.crate_versions));
+    println!(
+        "{} {} hits, {} successful, {} failed ({:.0}% success)",
+        "Usage:".bold(),
+        entry.hits,
+        entry.successes,
+        entry.failures,
+        entry.success_rate() * 100.0
+    );
+    println!("{}", "Patch:".bold());
+    for hunk in span_patch::parse_blocks(&entry.patch) {
+        let path = hunk.file.as_deref().unwrap_or("<file with the error>");
+        let before = (!hunk.search.is_empty()).then(|| hunk.search.join("\n") + "\n");
+        print_colored_diff(&unified_diff(path, before.as_deref(), &(hunk.replace.join("\n") + "\n")));
+    }
+    Ok(())
+}
+
+fn stats(cache: &KnowledgeCache) -> Result<()> {
+    let entries = cache.entries()?;
+    let hits: u32 = entries.iter().map(|e| e.hits).sum();
+    let successes: u32 = entries.iter().map(|e| e.successes).sum();
+    let failures: u32 = entries.iter().map(|e| e.failures).sum();
+
+    println!("{} {}", "Entries:".bold(), entries.len());
+    for (layer, path) in cache.locations() {
+        let size = std::fs::metadata(path).map_or(0, |m| m.len());
+        println!("  {layer}: {} ({})", path.display(), format_size(size));
+    }
+    println!("{} {hits}", "Cache hits:".bold());
+    println!("{} {successes} successful, {failures} failed", "Applications:".bold());
+    let oldest = entries.iter().min_by_key(|e| e.created_at);
+    let newest = entries.iter().max_by_key(|e| e.created_at);
+    if let (Some(oldest), Some(newest)) = (oldest, newest) {
+        println!(
+            "{} {} ago .. {} ago",
+            "Stored:".bold(),
+            format_age(oldest.age_secs()),
+            format_age(newest.age_secs())
+        );
+    }
+    print_counts("Error codes:", entries.iter().map(CachedFix::error_code));
+    print_counts("Toolchains:", entries.iter().map(|e| or_unknown(&e.toolchain)));
+    print_counts("Models:", entries.iter().map(|e| or_unknown(&e.model)));
+    Ok(())
+}
+
+fn print_counts<'a>(title: &str, values: impl Iterator<Item = &'a str>) {
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for value in values {
+        *counts.entry(value).or_default() += 1;
+    }
+    if counts.is_empty() {
+        return;
+    }
+    let mut counts: Vec<(&str, usize)> = counts.into_iter().collect();
+    counts.sort_by_key(|(_, n)| std::cmp::Reverse(*n));
+    println!("{}", title.bold());
+    for (value, n) in counts {
+        println!("  {n:>5}  {value}");
+    }
+}
+
+/// Удаляет записи старше `older_than` дней и/или с долей успехов ниже `min_success_rate`
+/// (только если попыток применения было не меньше `min_attempts`).
+fn prune(
+    cache: &KnowledgeCache,
+    older_than: Option<u64>,
+    min_success_rate: Option<f64>,
+    min_attempts: u32,
+    dry_run: bool,
+) -> Result<()> {
+    if older_than.is_none() && min_success_rate.is_none() {
+        bail!("Nothing to prune by: pass --older-than and/or --min-success-rate.");
+    }
+    let stale = |e: &CachedFix| older_than.is_some_and(|days| e.age_secs() > days as i64 * DAY_SECS);
+    let unreliable = |e: &CachedFix| {
+        min_success_rate.is_some_and(|rate| e.successes + e.failures >= min_attempts && e.success_rate() < rate)
+    };
+    let doomed: Vec<CachedFix> = cache.entries()?.into_iter().filter(|e| stale(e) || unreliable(e)).collect();
+    if doomed.is_empty() {
+        println!("{}", "No entries match.".green());
+        return Ok(());
+    }
+
+    for entry in &doomed {
+        let reason = if stale(entry) { "stale" } else { "unreliable" };
+        println!("{:>10}  {:<10}  {}", cache.label(entry), reason, truncate(&entry.signature, SIGNATURE_WIDTH));
+    }
+    if dry_run {
+        println!("{}", format!("{} entries would be removed.", doomed.len()).yellow());
+        return Ok(());
+    }
+    let ids: Vec<EntryRef> = doomed.iter().map(|e| EntryRef { layer: Some(e.layer), id: e.id }).collect();
+    let removed = cache.delete(&ids)?;
+    println!("{}", format!("Removed {removed} entries. Run `cache vacuum` to shrink the file.").green());
+    Ok(())
+}
+
+fn db_size(cache: &KnowledgeCache) -> u64 {
+    cache.locations().iter().map(|(_, path)| std::fs::metadata(path).map_or(0, |m| m.len())).sum()
+}
+
+/// Экспорт в JSONL: по записи на строку, сортировка по сигнатуре — чтобы diff
+/// общего файла в репозитории команды показывал только реальные изменения.
+fn export(cache: &KnowledgeCache, file: Option<&Path>, min_success_rate: Option<f64>) -> Result<()> {
+    let fixes: Vec<CachedFix> = cache
+        .export()?
+        .into_iter()
+        .filter(|f| min_success_rate.is_none_or(|rate| f.success_rate() >= rate))
+        .collect();
+    let mut out: Box<dyn Write> = match file {
+        Some(path) => Box::new(
+            std::fs::File::create(path).with_context(|| format!("Failed to create {}", path.display()))?,
+        ),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    for fix in &fixes {
+        writeln!(out, "{}", serde_json::to_string(fix)?)?;
+    }
+    out.flush()?;
+    if let Some(path) = file {
+        println!("Exported {} entries to {}.", fixes.len(), path.display());
+    }
+    Ok(())
+}
+
+fn read_jsonl(path: &Path) -> Result<Vec<CachedFix>> {
+    let file = std::fs::File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut fixes = Vec::new();
+    for (n, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fix = serde_json::from_str(&line)
+            .with_context(|| format!("{}:{}: not a cache entry", path.display(), n + 1))?;
+        fixes.push(fix);
+    }
+    Ok(fixes)
+}
+
+fn format_size(bytes: u64) -> String {
+    match bytes {
+        0..1024 => format!("{bytes} B"),
+        1024..1_048_576 => format!("{:.1} KiB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MiB", bytes as f64 / 1_048_576.0),
+    }
+}
+
+fn format_age(secs: i64) -> String {
+    let secs = secs.max(0);
+    match secs {
+        0..60 => format!("{secs}s"),
+        60..3600 => format!("{}m", secs / 60),
+        3600..DAY_SECS => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / DAY_SECS),
+    }
+}
+
+fn or_unknown(value: &str) -> &str {
+    if value.is_empty() { "unknown" } else { value }
+}
+
+fn truncate(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        return text.to_string();
+    }
+    let head: String = text.chars().take(width - 1).collect();
+    format!("{head}…")
+}
@@ -1,31 +1,40 @@
-use crate::{CargoMessage, CompilerMessage};
+use crate::{run_cargo_in, CompilerMessage};
 use super::llm_interface::{LLMInterface, CargoSuggestionDetails};
+use super::change_set::{ChangeSet, PatchSet};
+use super::approval::Review;
+use super::issue_detector::IssueClassification;
+use super::workspace::{ScratchCopy, Workspace};
 use anyhow::{Context, Result};
-use std::io::{BufReader, BufRead};
-use std::process::{Command, Stdio};
-use std::sync::{Arc, Mutex};
-use std::thread;
+use std::path::Path;
 use tokio::fs;
 use toml_edit::{DocumentMut, Item, Value, InlineTable, Array};
 
 pub struct CargoExpert <'a> {
     llm: &'a LLMInterface,
+    changes: &'a ChangeSet,
 }
 
 impl<'a> CargoExpert<'a> {
-    pub fn new(llm: &'a LLMInterface) -> Self { Self { llm } }
+    pub fn new(llm: &'a LLMInterface, changes: &'a ChangeSet) -> Self { Self { llm, changes } }
 
     /// Правит конкретный Cargo.toml по относительному пути `manifest_rel_path`
+    /// (`kind` — `MissingDependency` или `MissingFeature`).
     /// Возвращает Ok(true), если изменения применены (и проверка прошла).
-    pub async fn fix_manifest_issue_at(&self, issue: &CompilerMessage, manifest_rel_path: &str) -> Result<bool> {
-        println!("    -> Detected a potential Cargo.toml issue. Engaging Cargo Expert.");
+    pub async fn fix_manifest_issue_at(&self, issue: &CompilerMessage, manifest_rel_path: &str, kind: IssueClassification) -> Result<bool> {
+        println!("    -> Detected a potential Cargo.toml issue ({kind:?}). Engaging Cargo Expert.");
 
         // 1) Пытаемся спросить LLM
-        let suggestion = match self.llm.generate_cargo_fix(&issue.message).await {
+        let missing_feature = kind == IssueClassification::MissingFeature;
+        // модель видит, что уже подключено и с какими фичами, и не предлагает дубликат
+        let package_context = Workspace::load(Path::new("."))
+            .ok()
+            .and_then(|ws| ws.package_for_file(Path::new(manifest_rel_path)).map(|p| ws.describe_package(p)))
+            .unwrap_or_default();
+        let suggestion = match self.llm.generate_cargo_fix(&issue.prompt_text(), missing_feature, &package_context).await {
             Ok(s) => s,
             Err(e) => {
                 eprintln!("    -> LLM cargo suggestion failed: {e}. Using heuristic fallback.");
-                self.heuristic_for_common_errors(&issue.message)
+                self.heuristic_for_common_errors(&issue.full_text())
             }
         };
 
@@ -41,27 +50,36 @@ impl<'a> CargoExpert<'a> {
         let mut doc = original_content.parse::<DocumentMut>()
             .context("Failed to parse Cargo.toml")?;
 
-        // гарантируем наличие [dependencies]
-        if doc.get("dependencies").is_none() {
-            doc["dependencies"] = toml_edit::table();
+        // крейты для build.rs объявляются в [build-dependencies]
+        let for_build_script = issue.unit.as_ref().is_some_and(|u| u.build_script)
+            || issue.primary_span().is_some_and(|s| s.file_name.ends_with("build.rs"));
+        let section = if for_build_script { "build-dependencies" } else { "dependencies" };
+        if doc.get(section).is_none() {
+            doc[section] = toml_edit::table();
         }
 
-        if let Some(deps) = doc["dependencies"].as_table_mut() {
-            let dep_item = if suggestion.features.is_empty() {
-                Item::Value(Value::from(suggestion.version))
-            } else {
-                let mut table = InlineTable::new();
-                table.insert("version", Value::from(suggestion.version));
-                let mut features = Array::new();
-                for f in suggestion.features {
-                    features.push(f);
+        if let Some(deps) = doc[section].as_table_mut() {
+            match deps.get_mut(&suggestion.crate_name) {
+                // крейт уже подключён: оставляем его версию и добавляем недостающие фичи
+                Some(existing) => merge_features(existing, &suggestion.features),
+                None => {
+                    let dep_item = if suggestion.features.is_empty() {
+                        Item::Value(Value::from(suggestion.version))
+                    } else {
+                        let mut table = InlineTable::new();
+                        table.insert("version", Value::from(suggestion.version));
+                        let mut features = Array::new();
+                        for f in suggestion.features {
+                            features.push(f);
+                        }
+                        table.insert("features", Value::from(features));
+                        Item::Value(table.into())
+                    };
+                    deps.insert(&suggestion.crate_name, dep_item);
                 }
-                table.insert("features", Value::from(features));
-                Item::Value(table.into())
-            };
-            deps.insert(&suggestion.crate_name, dep_item);
+            }
         } else {
-            anyhow::bail!("Could not find or create [dependencies] table");
+            anyhow::bail!("Could not find or create [{section}] table");
         }
 
         let new_content = doc.to_string();
@@ -71,14 +89,29 @@ impl<'a> CargoExpert<'a> {
         }
 
         println!("    -> Verifying the suggested Cargo.toml changes...");
-        if self.verify_fix(manifest_rel_path, &new_content, &issue.message).await? {
-            println!("    -> Verification successful! Applying changes to {}.", manifest_rel_path);
-            fs::write(manifest_rel_path, new_content).await?;
-            Ok(true)
-        } else {
+        let set = PatchSet::single(manifest_rel_path, Some(original_content), new_content);
+        if !self.verify_fix(&set, &issue.message).await? {
             println!("    -> Verification failed. Skipping manifest change.");
-            Ok(false)
+            return Ok(false);
         }
+        let set = match self.changes.review(set).await? {
+            Review::Accept(set) => set,
+            // правку пользователя проверяем заново: она могла сломать манифест
+            Review::Edited(set) => {
+                if !self.verify_fix(&set, &issue.message).await? {
+                    println!("    -> Edited manifest change failed verification. Skipping.");
+                    return Ok(false);
+                }
+                set
+            }
+            Review::Reject(_) => {
+                println!("    -> Manifest change rejected by user.");
+                return Ok(false);
+            }
+        };
+        println!("    -> Verification successful! Applying changes to {}.", manifest_rel_path);
+        self.changes.apply(&set).await?;
+        Ok(true)
     }
 
     /// Простая эвристика на популярные ошибки манифеста.
@@ -111,96 +144,55 @@ impl<'a> CargoExpert<'a> {
         }
     }
 
-    async fn verify_fix(&self, manifest_rel_path: &str, new_cargo_toml: &str, original_error_message: &str) -> Result<bool> {
-        let tmp = tempfile::tempdir()?;
-        // копируем весь репо
-        copy_sources(".", tmp.path()).await?;
+    async fn verify_fix(&self, set: &PatchSet, original_error_message: &str) -> Result<bool> {
+        // копируем воркспейс (с path-зависимостями вне корня) и накладываем правку манифеста
+        let tmp = ScratchCopy::create(Path::new(".")).await?;
+        tmp.write_set(set).await?;
 
-        // перезаписываем КОНКРЕТНЫЙ манифест в копии
-        let manifest_dest = tmp.path().join(manifest_rel_path);
-        if let Some(parent) = manifest_dest.parent() {
-            tokio::fs::create_dir_all(parent).await.ok();
+        // сломанный манифест не даёт диагностик компилятора: cargo падает раньше
+        if let Err(e) = Workspace::load(tmp.path()) {
+            println!("    -> Manifest does not load: {}", format!("{e:#}").lines().next().unwrap_or_default());
+            return Ok(false);
         }
-        tokio::fs::write(&manifest_dest, new_cargo_toml).await?;
-
-        // собираем весь воркспейс/крейта из корня
-        let run_cargo = |what: &str| -> Result<(bool, Vec<CompilerMessage>)> {
-            let mut child = Command::new("cargo")
-                .current_dir(tmp.path())
-                .args([what, "--message-format=json"])
-                .stdout(Stdio::piped())
-                .stderr(Stdio::piped())
-                .spawn()?;
-
-            let msgs: Arc<Mutex<Vec<CompilerMessage>>> = Arc::new(Mutex::new(Vec::new()));
-            let msgs_out = Arc::clone(&msgs);
-            let msgs_err = Arc::clone(&msgs);
-
-            let mut ths = Vec::new();
-            if let Some(stdout) = child.stdout.take() {
-                ths.push(thread::spawn(move || {
-                    let r = BufReader::new(stdout);
-                    for line in r.lines().flatten() {
-                        if let Ok(m) = serde_json::from_str::<CargoMessage>(&line) {
-                            if m.reason == "compiler-message" {
-                                if let Some(cm) = m.message { msgs_out.lock().unwrap().push(cm); }
-                            }
-                        }
-                    }
-                }));
-            }
-            if let Some(stderr) = child.stderr.take() {
-                ths.push(thread::spawn(move || {
-                    let r = BufReader::new(stderr);
-                    for line in r.lines().flatten() {
-                        if let Ok(m) = serde_json::from_str::<CargoMessage>(&line) {
-                            if m.reason == "compiler-message" {
-                                if let Some(cm) = m.message { msgs_err.lock().unwrap().push(cm); }
-                            }
-                        }
-                    }
-                }));
-            }
-            for t in ths { t.join().unwrap(); }
-            let _status = child.wait()?;
-
-            let all = Arc::try_unwrap(msgs).unwrap().into_inner().unwrap();
-            let errors: Vec<&CompilerMessage> = all.iter().filter(|m| m.level == "error").collect();
-            let ok = if errors.is_empty() {
-                true
-            } else {
-                // фикс успешен, если исходная ошибка исчезла
-                !errors.iter().any(|e| e.message.contains(original_error_message))
-            };
-
-            Ok((ok, all))
-        };
 
-        let (ok, _msgs) = run_cargo("check")?;
-        Ok(ok)
+        // собираем весь воркспейс/крейт из корня копии
+        let messages = run_cargo_in(tmp.path(), &["check"])?;
+        // фикс успешен, если исходная ошибка исчезла
+        Ok(!messages
+            .iter()
+            .any(|m| m.level == "error" && m.message.contains(original_error_message)))
     }
 }
 
-async fn copy_sources(src: &str, dst: &std::path::Path) -> Result<()> {
-    let src_path = std::path::Path::new(src);
-    let mut stack = vec![src_path.to_path_buf()];
-    while let Some(path) = stack.pop() {
-        let meta = tokio::fs::metadata(&path).await?;
-        if meta.is_dir() {
-            let mut rd = tokio::fs::read_dir(&path).await?;
-            while let Some(ent) = rd.next_entry().await? {
-                let p = ent.path();
-                // пропускаем target/.git/локальную БД кэша
-                let s = p.to_string_lossy();
-                if s.contains("target") || s.contains(".git") || s.contains(".rusty_fixer_cache.db") { continue; }
-                stack.push(p);
-            }
-        } else {
-            let rel = path.strip_prefix(src_path).unwrap();
-            let dst_path = dst.join(rel);
-            if let Some(parent) = dst_path.parent() { tokio::fs::create_dir_all(parent).await?; }
-            tokio::fs::copy(&path, &dst_path).await?;
+/// Добавляет фичи к уже объявленной зависимости, сохраняя версию и прочие ключи.
+/// Короткая форма `crate = "1"` превращается в `{ version = "1", features = [...] }`.
+pub fn merge_features(dep: &mut Item, features: &[String]) {
+    if features.is_empty() {
+        return;
+    }
+    if let Some(version) = dep.as_str().map(str::to_string) {
+        let mut table = InlineTable::new();
+        table.insert("version", Value::from(version));
+        *dep = Item::Value(table.into());
+    }
+    let existing = match dep {
+        Item::Value(Value::InlineTable(t)) => t.get_mut("features"),
+        Item::Table(t) => t.get_mut("features").and_then(Item::as_value_mut),
+        _ => return,
+    };
+    let mut merged = existing.and_then(|v| v.as_array().cloned()).unwrap_or_default();
+    for f in features {
+        if !merged.iter().any(|v| v.as_str() == Some(f.as_str())) {
+            merged.push(f.as_str());
+        }
+    }
+    match dep {
+        Item::Value(Value::InlineTable(t)) => {
+            t.insert("features", Value::from(merged));
+        }
+        Item::Table(t) => {
+            t.insert("features", toml_edit::value(merged));
         }
+        _ => {}
     }
-    Ok(())
 }
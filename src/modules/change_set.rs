@@ -0,0 +1,256 @@
+use super::approval::{self, Review};
+use anyhow::{Context, Result};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::path::Path;
+use tokio::fs;
+use tokio::task::JoinHandle;
+
+/// Сколько строк контекста оставляем вокруг изменений в unified diff.
+const DIFF_CONTEXT: usize = 3;
+
+/// Набор правок по нескольким файлам, который проверяется и применяется целиком.
+#[derive(Debug, Clone, Default)]
+pub struct PatchSet {
+    pub files: BTreeMap<String, FileEdit>,
+}
+
+#[derive(Debug, Clone)]
+pub struct FileEdit {
+    /// Содержимое до правки; None — файл создаётся.
+    pub before: Option<String>,
+    pub after: String,
+}
+
+impl PatchSet {
+    pub fn single(path: &str, before: Option<String>, after: String) -> Self {
+        let mut set = Self::default();
+        set.insert(path, before, after);
+        set
+    }
+
+    pub fn insert(&mut self, path: &str, before: Option<String>, after: String) {
+        self.files.insert(path.to_string(), FileEdit { before, after });
+    }
+
+    pub fn unified_diff(&self) -> String {
+        self.files
+            .iter()
+            .map(|(path, edit)| unified_diff(path, edit.before.as_deref(), &edit.after))
+            .collect()
+    }
+}
+
+/// Единая точка записи проверенных правок (код и Cargo.toml).
+/// Запоминает исходное содержимое каждого файла, чтобы в конце отдать общий патч.
+pub struct ChangeSet {
+    /// path -> (содержимое до первой правки, текущее содержимое); None — файла не было
+    changes: RefCell<BTreeMap<String, (Option<String>, String)>>,
+    /// Запись, начатая `apply` и ещё не учтённая в `changes`, и пути, которые она пишет.
+    in_flight: RefCell<Option<JoinHandle<Result<Vec<Written>>>>>,
+    writing: RefCell<Vec<String>>,
+    interactive: bool,
+}
+
+/// Записанный файл: путь, прежнее содержимое (None — файла не было) и новое.
+type Written = (String, Option<String>, String);
+
+impl ChangeSet {
+    pub fn new(interactive: bool) -> Self {
+        Self {
+            changes: RefCell::new(BTreeMap::new()),
+            in_flight: RefCell::new(None),
+            writing: RefCell::new(Vec::new()),
+            interactive,
+        }
+    }
+
+    /// В интерактивном режиме показывает правку и спрашивает пользователя;
+    /// иначе сразу одобряет. Skip/quit возвращаются ошибкой `approval::Interrupt`.
+    pub async fn review(&self, set: PatchSet) -> Result<Review> {
+        if !self.interactive {
+            return Ok(Review::Accept(set));
+        }
+        approval::ask(set)
+    }
+
+    /// Применяет набор правок по принципу «всё или ничего»:
+    /// если запись какого-то файла упала, уже записанные файлы откатываются.
+    /// Сама запись идёт в отдельной задаче и доводится до конца, даже если
+    /// этот future отменили (watch-режим) — недописанный набор файлов хуже любого.
+    pub async fn apply(&self, set: &PatchSet) -> Result<()> {
+        self.settle().await?;
+        let files: Vec<(String, String)> =
+            set.files.iter().map(|(path, edit)| (path.clone(), edit.after.clone())).collect();
+        *self.writing.borrow_mut() = files.iter().map(|(path, _)| path.clone()).collect();
+        *self.in_flight.borrow_mut() = Some(tokio::spawn(write_all(files)));
+        self.settle().await
+    }
+
+    /// Дожидается записи, начатой `apply`, и заносит её в общий патч.
+    /// Watch-режим вызывает её после отмены исправления.
+    pub async fn settle(&self) -> Result<()> {
+        let Some(task) = self.in_flight.borrow_mut().take() else {
+            return Ok(());
+        };
+        let result = task.await.context("File write task panicked");
+        self.writing.borrow_mut().clear();
+
+        let mut changes = self.changes.borrow_mut();
+        for (path, previous, content) in result?? {
+            changes
+                .entry(path)
+                .and_modify(|(_, after)| *after = content.clone())
+                .or_insert((previous, content));
+        }
+        Ok(())
+    }
+
+    /// Наша ли это запись: файл пишется прямо сейчас или на диске лежит ровно то,
+    /// что мы записали последним. Так watch-режим отличает свои записи от чужих.
+    pub fn is_own_write(&self, path: &Path) -> bool {
+        if self.writing.borrow().iter().any(|p| same_file(Path::new(p), path)) {
+            return true;
+        }
+        self.changes.borrow().iter().any(|(p, (_, after))| {
+            same_file(Path::new(p), path) && std::fs::read_to_string(path).is_ok_and(|c| &c == after)
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.borrow().values().all(|(before, after)| before.as_deref() == Some(after.as_str()))
+    }
+
+    /// Все накопленные правки одним unified diff (в формате `git apply`).
+    pub fn unified_diff(&self) -> String {
+        let mut out = String::new();
+        for (path, (before, after)) in self.changes.borrow().iter() {
+            out.push_str(&unified_diff(path, before.as_deref(), after));
+        }
+        out
+    }
+}
+
+/// Записывает все файлы; при ошибке откатывает уже записанные.
+async fn write_all(files: Vec<(String, String)>) -> Result<Vec<Written>> {
+    let mut written: Vec<Written> = Vec::new();
+    for (path, content) in files {
+        match write_file(&path, &content).await {
+            Ok((previous, content)) => written.push((path, previous, content)),
+            Err(e) => {
+                for (path, previous, _) in written.iter().rev() {
+                    let restored = match previous {
+                        Some(content) => fs::write(path, content).await,
+                        None => fs::remove_file(path).await,
+                    };
+                    if let Err(re) = restored {
+                        eprintln!("    -> Failed to roll back {}: {re}", path);
+                    }
+                }
+                return Err(e);
+            }
+        }
+    }
+    Ok(written)
+}
+
+/// Один и тот же ли это файл (пути могут быть относительными и абсолютными).
+pub fn same_file(a: &Path, b: &Path) -> bool {
+    if a == b {
+        return true;
+    }
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Записывает файл; возвращает прежнее содержимое и то, что реально записано.
+async fn write_file(path: &str, new_content: &str) -> Result<(Option<String>, String)> {
+    let current = fs::read_to_string(path).await.ok();
+
+    // LLM обычно обрезает завершающий перевод строки — сохраняем его, если он был
+    let mut new_content = new_content.to_string();
+    if current.as_deref().is_some_and(|c| c.ends_with('\n')) && !new_content.ends_with('\n') {
+        new_content.push('\n');
+    }
+
+    if let Some(parent) = std::path::Path::new(path).parent() {
+        if !parent.as_os_str().is_empty() {
+            fs::create_dir_all(parent).await.ok();
+        }
+    }
+    fs::write(path, &new_content).await
+        .with_context(|| format!("Failed to write {}", path))?;
+    Ok((current, new_content))
+}
+
+enum Line<'a> {
+    Keep(&'a str),
+    Del(&'a str),
+    Add(&'a str),
+}
+
+/// Строит unified diff одного файла; `old == None` означает новый файл.
+pub fn unified_diff(path: &str, old: Option<&str>, new: &str) -> String {
+    let old_text = old.unwrap_or("");
+    if old_text == new {
+        return String::new();
+    }
+
+    let old_lines: Vec<&str> = old_text.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let ops: Vec<Line> = diff::slice(&old_lines, &new_lines)
+        .into_iter()
+        .map(|r| match r {
+            diff::Result::Both(l, _) => Line::Keep(l),
+            diff::Result::Left(l) => Line::Del(l),
+            diff::Result::Right(l) => Line::Add(l),
+        })
+        .collect();
+
+    let changed: Vec<usize> = ops
+        .iter()
+        .enumerate()
+        .filter(|(_, op)| !matches!(op, Line::Keep(_)))
+        .map(|(i, _)| i)
+        .collect();
+    if changed.is_empty() {
+        return String::new();
+    }
+
+    let mut out = String::new();
+    let from = if old.is_some() { format!("a/{path}") } else { "/dev/null".to_string() };
+    out.push_str(&format!("--- {from}\n+++ b/{path}\n"));
+
+    // группируем изменения в ханки, сливая близкие (контексты пересекаются)
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for &i in &changed {
+        let start = i.saturating_sub(DIFF_CONTEXT);
+        let end = (i + DIFF_CONTEXT + 1).min(ops.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    for (start, end) in hunks {
+        let old_before = ops[..start].iter().filter(|op| !matches!(op, Line::Add(_))).count();
+        let new_before = ops[..start].iter().filter(|op| !matches!(op, Line::Del(_))).count();
+        let hunk = &ops[start..end];
+        let old_count = hunk.iter().filter(|op| !matches!(op, Line::Add(_))).count();
+        let new_count = hunk.iter().filter(|op| !matches!(op, Line::Del(_))).count();
+        let old_start = if old_count == 0 { old_before } else { old_before + 1 };
+        let new_start = if new_count == 0 { new_before } else { new_before + 1 };
+
+        out.push_str(&format!("@@ -{old_start},{old_count} +{new_start},{new_count} @@\n"));
+        for op in hunk {
+            match op {
+                Line::Keep(l) => out.push_str(&format!(" {l}\n")),
+                Line::Del(l) => out.push_str(&format!("-{l}\n")),
+                Line::Add(l) => out.push_str(&format!("+{l}\n")),
+            }
+        }
+    }
+    out
+}
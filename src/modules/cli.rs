@@ -1,4 +1,7 @@
-use clap::Parser;
+use super::knowledge_cache::EntryRef;
+use super::llm_backend::DEFAULT_TIMEOUT_SECS;
+use clap::{Parser, Subcommand, ValueEnum};
+use std::path::PathBuf;
 
 /// RustyFixerAI: An autonomous AI assistant to fix Rust compilation errors.
 ///
@@ -7,20 +10,247 @@ use clap::Parser;
 #[derive(Parser, Debug)]
 #[command(version = "2.0.0", author = "You", about, long_about = None)]
 pub struct CliArgs {
+    #[command(subcommand)]
+    pub command: Option<CliCommand>,
+
+    /// Which knowledge cache to use: `project` (`.rusty_fixer_cache.db` in the
+    /// project root), `user` (shared by all projects, under $XDG_DATA_HOME) or
+    /// `layered` (project first, then user; new fixes are saved to both).
+    #[arg(long, value_enum, value_name = "SCOPE", env = "RUSTY_FIXER_CACHE_SCOPE", default_value_t = CacheScope::Project, global = true)]
+    pub cache_scope: CacheScope,
+
     /// Enables an additional pass to fix warnings after all errors are resolved.
     #[arg(long, default_value_t = false)]
     pub fix_warnings: bool,
 
+    /// After the build is clean, runs `cargo clippy` and fixes its lints: first all
+    /// machine-applicable suggestions at once (like `cargo clippy --fix`), then the
+    /// remaining lints one by one with the LLM. `#![deny]` lints are treated as warnings.
+    #[arg(long, default_value_t = false)]
+    pub clippy: bool,
+
+    /// Extra clippy lints or groups to enable, e.g. `pedantic,nursery,unwrap_used`
+    /// (passed as `-W clippy::<name>`).
+    #[arg(long, value_delimiter = ',', value_name = "LINT", requires = "clippy")]
+    pub clippy_lints: Vec<String>,
+
+    /// Clippy lints or groups to allow (passed as `-A clippy::<name>` after the enabled ones).
+    #[arg(long, value_delimiter = ',', value_name = "LINT", requires = "clippy")]
+    pub clippy_allow: Vec<String>,
+
+    /// After the build is clean, runs `cargo test` and fixes failing tests by
+    /// changing the code under test. The tests themselves are never edited.
+    #[arg(long, default_value_t = false)]
+    pub fix_tests: bool,
+
+    /// Warning categories (lint names such as `unused_imports`, `dead_code`) the
+    /// warnings pass is allowed to fix. When empty, every category is eligible.
+    #[arg(long, value_delimiter = ',', value_name = "LINT")]
+    pub only_warnings: Vec<String>,
+
+    /// Warning categories the warnings pass must never touch.
+    #[arg(long, value_delimiter = ',', value_name = "LINT")]
+    pub skip_warnings: Vec<String>,
+
     /// Forces the agent to ignore the local knowledge cache and always
     /// search online for solutions. Useful for getting the freshest fixes.
     #[arg(long, default_value_t = false)]
     pub no_cache: bool,
 
-    /// [NOT IMPLEMENTED] Runs the tool in watch mode, automatically fixing on save.
+    /// Also applies rustc suggestions marked `MaybeIncorrect` in the offline
+    /// pre-pass (by default only `MachineApplicable` ones are used).
+    #[arg(long, default_value_t = false)]
+    pub allow_maybe_incorrect: bool,
+
+    /// Asks the model for the whole corrected file instead of targeted
+    /// SEARCH/REPLACE edits around the compiler span (legacy behaviour).
+    #[arg(long, default_value_t = false)]
+    pub full_file: bool,
+
+    /// Fixes every error in the same file (or the same function) as the selected
+    /// one with a single combined LLM edit. A fix that removes some of the targeted
+    /// errors without introducing new ones is accepted.
+    #[arg(long, value_enum, value_name = "SCOPE")]
+    pub batch: Option<BatchScope>,
+
+    /// Shows every verified fix as a coloured diff and asks whether to accept,
+    /// reject, edit, skip the issue or quit before anything is written.
+    #[arg(long, default_value_t = false)]
+    pub interactive: bool,
+
+    /// Runs the whole pipeline on a scratch copy of the project and prints every
+    /// verified change as one unified diff instead of touching the working tree.
+    #[arg(long, default_value_t = false, conflicts_with = "watch")]
+    pub dry_run: bool,
+
+    /// Writes the dry-run patch to this file instead of stdout.
+    #[arg(long, value_name = "FILE", requires = "dry_run")]
+    pub patch_out: Option<PathBuf>,
+
+    /// LLM provider: `ollama`, `openai` (any OpenAI-compatible server such as
+    /// vLLM, LM Studio or llama.cpp server) or `llamacpp` (native `/completion`).
+    /// Defaults to `ollama`.
+    #[arg(long, value_name = "BACKEND", env = "RUSTY_FIXER_LLM_BACKEND")]
+    pub llm_backend: Option<String>,
+
+    /// Base URL of the LLM server.
+    #[arg(long, value_name = "URL", env = "RUSTY_FIXER_LLM_URL")]
+    pub llm_url: Option<String>,
+
+    /// Model name sent to the LLM server.
+    #[arg(long, value_name = "MODEL", env = "RUSTY_FIXER_LLM_MODEL")]
+    pub llm_model: Option<String>,
+
+    /// API key for OpenAI-compatible servers that require one. OPENAI_API_KEY is
+    /// used as a fallback only when the server is api.openai.com.
+    #[arg(long, value_name = "KEY", env = "RUSTY_FIXER_LLM_API_KEY", hide_env_values = true)]
+    pub llm_api_key: Option<String>,
+
+    /// Timeout of a single LLM request, in seconds.
+    #[arg(long, value_name = "SECS", env = "RUSTY_FIXER_LLM_TIMEOUT", default_value_t = DEFAULT_TIMEOUT_SECS)]
+    pub llm_timeout: u64,
+
+    /// Records every LLM prompt/response pair into this JSONL fixture.
+    /// Web search and the knowledge cache are disabled so the recorded prompts are reproducible.
+    #[arg(long, value_name = "FILE", env = "RUSTY_FIXER_LLM_RECORD", conflicts_with = "llm_replay")]
+    pub llm_record: Option<PathBuf>,
+
+    /// Serves LLM responses from a fixture recorded with --llm-record
+    /// instead of calling a model server. Web search and the knowledge cache are disabled.
+    #[arg(long, value_name = "FILE", env = "RUSTY_FIXER_LLM_REPLAY")]
+    pub llm_replay: Option<PathBuf>,
+
+    /// Skips the online search step; prompts get no web context.
+    #[arg(long, default_value_t = false)]
+    pub no_web: bool,
+
+    /// Runs the tool in watch mode: sources and Cargo.toml files are monitored,
+    /// and newly appearing errors are fixed automatically on save.
     #[arg(long, default_value_t = false)]
     pub watch: bool,
 }
 
+/// Отдельные команды; без команды запускается исправление проекта.
+#[derive(Subcommand, Debug)]
+pub enum CliCommand {
+    /// Inspects and curates the local knowledge cache of verified fixes.
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum CacheAction {
+    /// Lists stored fixes with their reuse and verification counters.
+    List {
+        /// Sort order of the listing.
+        #[arg(long, value_enum, default_value_t = CacheSort::Recent)]
+        sort: CacheSort,
+
+        /// Shows at most this many entries.
+        #[arg(long, value_name = "N")]
+        limit: Option<usize>,
+    },
+
+    /// Shows one entry: signature, metadata and the stored patch.
+    Show {
+        /// Entry id as printed by `cache list` (`user:12` / `project:12` with a layered cache).
+        id: EntryRef,
+    },
+
+    /// Prints totals: entries, hits, replay outcomes, error codes, toolchains and models.
+    Stats,
+
+    /// Deletes entries by id.
+    Delete {
+        /// Entry ids as printed by `cache list`.
+        #[arg(required = true)]
+        ids: Vec<EntryRef>,
+    },
+
+    /// Evicts stale or unreliable entries. At least one criterion is required.
+    Prune {
+        /// Removes entries stored more than this many days ago.
+        #[arg(long, value_name = "DAYS")]
+        older_than: Option<u64>,
+
+        /// Removes entries whose share of successful applications is below this
+        /// value (0.0–1.0), once they have been tried at least --min-attempts times.
+        #[arg(long, value_name = "RATE")]
+        min_success_rate: Option<f64>,
+
+        /// Attempts (successes + failures) needed before the success rate is judged.
+        #[arg(long, value_name = "N", default_value_t = 3, requires = "min_success_rate")]
+        min_attempts: u32,
+
+        /// Only prints the entries that would be removed.
+        #[arg(long, default_value_t = false)]
+        dry_run: bool,
+    },
+
+    /// Compacts the database file after deletions.
+    Vacuum,
+
+    /// Writes entries as JSON lines (one fix per line) for sharing, e.g. in a team repo.
+    Export {
+        /// Output file; stdout when omitted.
+        file: Option<PathBuf>,
+
+        /// Exports only entries with at least this share of successful applications (0.0–1.0).
+        #[arg(long, value_name = "RATE")]
+        min_success_rate: Option<f64>,
+    },
+
+    /// Loads entries exported with `cache export` into the selected cache.
+    Import {
+        /// JSONL file written by `cache export`.
+        file: PathBuf,
+
+        /// What to do when an entry with the same signature already exists.
+        #[arg(long, value_enum, default_value_t = ConflictPolicy::Better)]
+        on_conflict: ConflictPolicy,
+    },
+}
+
+/// Где хранится кэш решений.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CacheScope {
+    Project,
+    User,
+    Layered,
+}
+
+/// Разрешение конфликтов при импорте кэша.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Keep the existing entry.
+    Keep,
+    /// Overwrite with the imported entry.
+    Replace,
+    /// Keep whichever entry was stored more recently.
+    Newer,
+    /// Keep the entry with the higher success rate (then more attempts, then newer).
+    Better,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CacheSort {
+    /// Newest first.
+    Recent,
+    /// Most reused first.
+    Hits,
+    /// Lowest success rate first.
+    Failures,
+}
+
+/// Какие ошибки объединяются в одну правку с выбранной.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BatchScope {
+    File,
+    Function,
+}
+
 pub fn parse_args() -> CliArgs {
     CliArgs::parse()
 }
@@ -1,37 +1,467 @@
 use crate::CompilerMessage;
+use super::cli::BatchScope;
+use super::project_analyzer;
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+/// Класс проблемы определяет, кто её чинит (см. `main::Agent::handle_issue`): манифест —
+/// `CargoExpert`, линковку и build.rs — свои эксперты. Классы ошибок кода идут через общий
+/// `PatchEngine` и различаются только подсказкой модели (`strategy_hint`) и очерёдностью (`rank`).
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum IssueClassification {
-    Code,
-    CargoManifest,
+    /// Нет нужного крейта в Cargo.toml.
+    MissingDependency,
+    /// Крейт подключён, но без нужной cargo-фичи.
+    MissingFeature,
+    /// Не найден путь/имя внутри проекта: use, модули, типы, значения.
+    Import,
+    TypeMismatch,
+    BorrowCheck,
+    TraitBound,
+    /// Нет метода или поля у типа получателя (опечатка, не тот тип, трейт не импортирован).
+    MethodResolution,
+    /// Компилятор не может вывести тип: нужна аннотация.
+    TypeInference,
+    Macro,
+    Lifetime,
     Linker,
+    BuildScript,
+    /// Прочие ошибки кода.
+    Code,
     Unknown,
 }
 
+impl IssueClassification {
+    /// Подсказка для модели: на что смотреть при исправлении ошибок этого класса.
+    /// Сбор контекста, подсказки rustc и проверка правки у классов ошибок кода общие.
+    pub fn strategy_hint(self) -> &'static str {
+        match self {
+            IssueClassification::Import => "The error is a name-resolution problem. Prefer fixing `use` paths, \
+                `mod` declarations and visibility (`pub`) over changing logic.",
+            IssueClassification::TypeMismatch => "The error is a type mismatch. Convert the value at the reported span \
+                (`.into()`, `as`, `&`/`*`, `.to_string()`, `?`) or adjust the annotation; keep function signatures unless they are wrong.",
+            IssueClassification::BorrowCheck => "The error comes from the borrow checker. Restructure ownership: shorten borrows, \
+                clone where cheap, use `mut` where required; do not add `unsafe`.",
+            IssueClassification::TraitBound => "The error is a missing trait implementation or bound. Add the derive/impl, \
+                import the trait providing the method, or add the bound to the generic parameter.",
+            IssueClassification::MethodResolution => "The error is an unknown method or field. Check the receiver's type: \
+                fix a misspelled name, call it on the right value (`.as_ref()`, `.iter()`, dereference), \
+                or import the trait that provides the method.",
+            IssueClassification::TypeInference => "The compiler cannot infer a type. Add an annotation at the reported span: \
+                a type on the `let` binding, a turbofish (`collect::<Vec<_>>()`, `parse::<T>()`) or a typed closure \
+                argument; do not change the logic.",
+            IssueClassification::Macro => "The error is inside a macro invocation or expansion. Fix the macro input \
+                (tokens, arguments) or import the macro; do not expand the macro by hand.",
+            IssueClassification::BuildScript => "The build script (build.rs) failed while running. Fix build.rs \
+                so it succeeds: handle missing environment variables, files or tools with a clear fallback, keep \
+                the `cargo:` directives it prints, and do not remove what it generates.",
+            IssueClassification::Lifetime => "The error is about lifetimes. Add or correct explicit lifetime parameters \
+                and annotations, or return owned data instead of references.",
+            _ => "",
+        }
+    }
+
+    /// Ошибки, которые исправляются правкой исходного кода (а не манифеста или окружения).
+    pub fn is_code_fix(self) -> bool {
+        !matches!(
+            self,
+            IssueClassification::MissingDependency
+                | IssueClassification::MissingFeature
+                | IssueClassification::Linker
+                | IssueClassification::BuildScript
+        )
+    }
+
+    /// Чем меньше, тем раньше чинится: ошибки сборки и манифеста порождают каскады в коде,
+    /// а borrow checker и линковка доходят до дела, только когда остальное уже собирается.
+    fn rank(self) -> u8 {
+        match self {
+            IssueClassification::BuildScript => 0,
+            IssueClassification::MissingDependency => 1,
+            IssueClassification::MissingFeature => 2,
+            IssueClassification::Import => 3,
+            IssueClassification::Macro => 4,
+            IssueClassification::TraitBound => 5,
+            IssueClassification::MethodResolution => 6,
+            IssueClassification::TypeMismatch => 7,
+            IssueClassification::TypeInference => 8,
+            IssueClassification::Lifetime => 9,
+            IssueClassification::Code => 10,
+            IssueClassification::BorrowCheck => 11,
+            IssueClassification::Linker => 12,
+            IssueClassification::Unknown => 13,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct DetectedIssue {
     pub classification: IssueClassification,
     pub message: CompilerMessage,
 }
 
+/// Выбирает ошибку, исправление которой вероятнее всего уберёт больше всего других.
+/// Порядок: настоящие ошибки раньше последствий; build.rs и зависимости раньше зависимых
+/// крейтов; манифест и импорты раньше ошибок типов; среди равных — та, от которой зависит
+/// больше других ошибок; затем по месту в файле.
 pub fn prioritize_and_classify(errors: &[CompilerMessage]) -> Option<DetectedIssue> {
-    errors.first().map(|msg| DetectedIssue {
-        classification: classify_message(msg),
-        message: msg.clone(),
-    })
+    let classes: Vec<IssueClassification> = errors.iter().map(classify_message).collect();
+    let roots = group_by_root_cause(errors, &classes);
+
+    let best = (0..errors.len()).min_by_key(|&i| {
+        let msg = &errors[i];
+        let follow_up = roots[i] != i || is_follow_up(msg);
+        let (build_script, order) = msg.unit.as_ref().map_or((false, usize::MAX), |u| (u.build_script, u.order));
+        let dependents = roots.iter().filter(|&&r| r == i).count();
+        let (file, line) = msg.primary_span().map_or(("", usize::MAX), |s| (s.file_name.as_str(), s.line_start));
+        (follow_up, !build_script, order, classes[i].rank(), std::cmp::Reverse(dependents), file, line)
+    })?;
+
+    Some(DetectedIssue { classification: classes[best], message: errors[best].clone() })
+}
+
+/// Ошибки, которые чинятся одной правкой вместе с выбранной: в том же файле (или в той же
+/// функции), исправляемые в коде и не являющиеся последствиями. Выбранная ошибка идёт первой.
+pub fn batch_for(issue: &DetectedIssue, errors: &[CompilerMessage], scope: BatchScope) -> Vec<CompilerMessage> {
+    let mut batch = vec![issue.message.clone()];
+    let Some(primary) = issue.message.primary_span() else {
+        return batch;
+    };
+    let function = match scope {
+        BatchScope::File => None,
+        BatchScope::Function => {
+            let source = std::fs::read_to_string(&primary.file_name).unwrap_or_default();
+            match project_analyzer::enclosing_function(&source, primary.line_start) {
+                Some(range) => Some(range),
+                // ошибка вне функции — объединять не с чем
+                None => return batch,
+            }
+        }
+    };
+
+    for err in errors {
+        let Some(span) = err.primary_span() else { continue };
+        let same_error = err.message == issue.message.message
+            && span.line_start == primary.line_start
+            && span.column_start == primary.column_start;
+        let in_scope = span.file_name == primary.file_name
+            && function.is_none_or(|(start, end)| (start..=end).contains(&span.line_start));
+        if !same_error && in_scope && classify_message(err).is_code_fix() && !is_follow_up(err) {
+            batch.push(err.clone());
+        }
+    }
+    batch
+}
+
+/// Для каждой ошибки — индекс ошибки-причины (или её собственный индекс).
+/// Причиной считается ошибка манифеста, импорта или макроса, чьё неразрешённое имя
+/// упоминается в другой ошибке: `unresolved import `serde_json::Value`` порождает
+/// `cannot find type `Value``. Ошибки на одном и том же месте сводятся к первой из них.
+fn group_by_root_cause(errors: &[CompilerMessage], classes: &[IssueClassification]) -> Vec<usize> {
+    let names: Vec<Vec<String>> = errors.iter().map(|e| quoted_names(&e.message)).collect();
+    let mut roots: Vec<usize> = (0..errors.len()).collect();
+
+    for (i, err) in errors.iter().enumerate() {
+        let same_place = (0..i).find(|&j| {
+            let (a, b) = (errors[j].primary_span(), err.primary_span());
+            a.zip(b).is_some_and(|(a, b)| {
+                a.file_name == b.file_name && a.line_start == b.line_start && a.column_start == b.column_start
+            })
+        });
+        if let Some(j) = same_place {
+            roots[i] = roots[j];
+            continue;
+        }
+        if is_root_cause(err, classes[i]) {
+            continue;
+        }
+        let cause = (0..errors.len()).find(|&j| {
+            j != i && is_root_cause(&errors[j], classes[j]) && names[j].iter().any(|n| names[i].contains(n))
+        });
+        if let Some(j) = cause {
+            roots[i] = j;
+        }
+    }
+    roots
 }
 
+/// Неразрешённый импорт или путь (E0432, E0433), отсутствующий крейт или макрос.
+fn is_root_cause(message: &CompilerMessage, class: IssueClassification) -> bool {
+    let code = message.code.as_ref().map(|c| c.code.as_str());
+    matches!(
+        class,
+        IssueClassification::MissingDependency | IssueClassification::MissingFeature | IssueClassification::Macro
+    ) || matches!(code, Some("E0432" | "E0433"))
+}
+
+/// Имена в обратных кавычках и их последний сегмент: `serde_json::Value` даёт оба варианта.
+fn quoted_names(text: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    for quoted in text.split('`').skip(1).step_by(2) {
+        names.push(quoted.to_string());
+        if let Some(last) = quoted.rsplit("::").next().filter(|l| !l.is_empty() && *l != quoted) {
+            names.push(last.to_string());
+        }
+    }
+    names
+}
+
+/// Вторичные сообщения: итоговые «aborting due to ...» / «could not compile ...», ошибки над
+/// уже ошибочными типами и «type annotations needed», которые обычно исчезают после исправления
+/// основной ошибки. Прочие сообщения без кода и спанов (падение build.rs, ошибки линковки) —
+/// первопричины, а не последствия.
+fn is_follow_up(message: &CompilerMessage) -> bool {
+    let text = message.message.to_lowercase();
+    let code = message.code.as_ref().map(|c| c.code.as_str());
+    text.starts_with("aborting due to")
+        || text.starts_with("could not compile")
+        || text.contains("due to previous error")
+        || (text.contains("due to ") && text.contains(" previous errors"))
+        || text.contains("{type error}")
+        || text.contains("{unknown}")
+        || matches!(code, Some("E0282" | "E0283"))
+}
+
+/// Стабильный ключ сообщения без номера строки: правки выше по файлу
+/// не должны превращать старую ошибку или предупреждение в «новые».
+pub fn issue_key(message: &CompilerMessage) -> String {
+    let code = message.code.as_ref().map(|c| c.code.as_str()).unwrap_or("");
+    let file = message.primary_span().map(|s| s.file_name.as_str()).unwrap_or("");
+    format!("{}|{}|{}", code, file, message.message)
+}
+
+/// Категория предупреждения — имя линта (`unused_imports`, `dead_code`, ...).
+pub fn warning_category(message: &CompilerMessage) -> Option<&str> {
+    message.code.as_ref().map(|c| c.code.as_str())
+}
+
+/// Отбирает предупреждения, которые разрешено чинить.
+/// `only` — если не пуст, чиним только эти категории; `skip` — никогда не трогаем.
+/// Линты clippy можно указывать и без префикса: `needless_return` = `clippy::needless_return`.
+pub fn filter_warnings(warnings: &[CompilerMessage], only: &[String], skip: &[String]) -> Vec<CompilerMessage> {
+    let matches = |category: &str, name: &String| category == name || category.strip_prefix("clippy::") == Some(name.as_str());
+    warnings
+        .iter()
+        .filter(|w| !w.spans.is_empty())
+        .filter(|w| {
+            let category = warning_category(w);
+            let allowed = only.is_empty() || category.is_some_and(|c| only.iter().any(|o| matches(c, o)));
+            let denied = category.is_some_and(|c| skip.iter().any(|s| matches(c, s)));
+            allowed && !denied
+        })
+        .cloned()
+        .collect()
+}
+
+const MISSING_DEPENDENCY_CODES: &[&str] = &["E0463"];
+const IMPORT_CODES: &[&str] = &["E0405", "E0412", "E0422", "E0425", "E0432", "E0433", "E0603", "E0659"];
+const TYPE_MISMATCH_CODES: &[&str] = &["E0053", "E0061", "E0069", "E0070", "E0308", "E0369", "E0560", "E0605", "E0606", "E0614"];
+const BORROW_CHECK_CODES: &[&str] = &[
+    "E0373", "E0382", "E0384", "E0499", "E0502", "E0503", "E0505", "E0506", "E0507", "E0508", "E0509", "E0515", "E0596", "E0597", "E0716",
+];
+const TRAIT_BOUND_CODES: &[&str] = &["E0038", "E0046", "E0117", "E0119", "E0186", "E0191", "E0277"];
+const METHOD_RESOLUTION_CODES: &[&str] = &["E0599", "E0609", "E0615"];
+const TYPE_INFERENCE_CODES: &[&str] = &["E0282", "E0283", "E0284"];
+const LIFETIME_CODES: &[&str] = &["E0106", "E0261", "E0262", "E0263", "E0495", "E0621", "E0623", "E0700", "E0726", "E0759"];
+
 fn classify_message(message: &CompilerMessage) -> IssueClassification {
-    let error_text = &message.message;
-    let cargo_keywords = [
-        "cannot find crate",
-        "can't find crate",
-        "unresolved import",
-        "no such extern crate",
-        "cannot find derive macro",
-    ];
-    if cargo_keywords.iter().any(|&kw| error_text.contains(kw)) {
-        return IssueClassification::CargoManifest;
+    // help/note-подсказки и метки спанов учитываем наравне с сообщением
+    let text = message.full_text().to_lowercase();
+    let has = |patterns: &[&str]| patterns.iter().any(|p| text.contains(p));
+    let code = message.code.as_ref().map(|c| c.code.as_str());
+    let code_in = |codes: &[&str]| code.is_some_and(|c| codes.contains(&c));
+
+    // Ошибки линковки и build.rs приходят без кода и часто без спанов
+    if has(&["linking with `", "linker `", "undefined reference to", "could not find native static library"]) {
+        return IssueClassification::Linker;
+    }
+    // ошибки компиляции самого build.rs — обычные ошибки кода; здесь — падение скрипта при запуске
+    if message.message.starts_with("failed to run custom build command") {
+        return IssueClassification::BuildScript;
+    }
+
+    if has(&["gated behind the `", "only a trait, without a derive macro"]) {
+        return IssueClassification::MissingFeature;
+    }
+    if code_in(MISSING_DEPENDENCY_CODES)
+        || has(&[
+            "can't find crate",
+            "cannot find crate",
+            "no such extern crate",
+            "undeclared crate",
+            "unlinked crate",
+            "you might be missing crate",
+            "cargo add",
+            "cannot find derive macro",
+        ])
+    {
+        return IssueClassification::MissingDependency;
+    }
+
+    if code_in(IMPORT_CODES) {
+        return IssueClassification::Import;
+    }
+    if code_in(TYPE_MISMATCH_CODES) {
+        return IssueClassification::TypeMismatch;
+    }
+    if code_in(BORROW_CHECK_CODES) {
+        return IssueClassification::BorrowCheck;
+    }
+    if code_in(TRAIT_BOUND_CODES) {
+        return IssueClassification::TraitBound;
+    }
+    if code_in(METHOD_RESOLUTION_CODES) {
+        return IssueClassification::MethodResolution;
+    }
+    if code_in(TYPE_INFERENCE_CODES) {
+        return IssueClassification::TypeInference;
+    }
+    if code_in(LIFETIME_CODES) || has(&["lifetime"]) {
+        return IssueClassification::Lifetime;
+    }
+    let in_macro = message.primary_span().is_some_and(|s| s.expansion.is_some());
+    if in_macro || has(&["cannot find macro", "no rules expected", "proc-macro derive panicked", "recursion limit reached"]) {
+        return IssueClassification::Macro;
+    }
+
+    if message.spans.is_empty() {
+        return IssueClassification::Unknown;
     }
     IssueClassification::Code
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn error(code: Option<&str>, message: &str) -> CompilerMessage {
+        error_at(code, message, 1)
+    }
+
+    fn error_at(code: Option<&str>, message: &str, line: usize) -> CompilerMessage {
+        serde_json::from_value(json!({
+            "message": message,
+            "level": "error",
+            "code": code.map(|c| json!({ "code": c })),
+            "spans": [{ "file_name": "src/main.rs", "line_start": line, "column_start": 5, "is_primary": true }],
+        }))
+        .unwrap()
+    }
+
+    fn in_unit(mut message: CompilerMessage, order: usize, build_script: bool) -> CompilerMessage {
+        message.unit = Some(crate::CompilationUnit { order, build_script, ..Default::default() });
+        message
+    }
+
+    fn selected(errors: &[CompilerMessage]) -> String {
+        prioritize_and_classify(errors).unwrap().message.message
+    }
+
+    #[test]
+    fn classifies_representative_errors() {
+        use IssueClassification::*;
+        let cases = [
+            (Some("E0463"), "can't find crate for `rand`", MissingDependency),
+            (None, "cannot find derive macro `Serialize` in this scope", MissingDependency),
+            (Some("E0425"), "cannot find function `spawn` in crate `tokio`: the item is gated behind the `rt` feature", MissingFeature),
+            (Some("E0432"), "unresolved import `crate::utils`", Import),
+            (Some("E0308"), "mismatched types", TypeMismatch),
+            (Some("E0382"), "borrow of moved value: `v`", BorrowCheck),
+            (Some("E0277"), "`Foo` doesn't implement `std::fmt::Display`", TraitBound),
+            (Some("E0599"), "no method named `lenght` found for struct `Vec<i32>` in the current scope", MethodResolution),
+            (Some("E0609"), "no field `nmae` on type `User`", MethodResolution),
+            (Some("E0282"), "type annotations needed", TypeInference),
+            (Some("E0283"), "type annotations needed: cannot satisfy `_: FromStr`", TypeInference),
+            (Some("E0106"), "missing lifetime specifier", Lifetime),
+            (None, "no rules expected the token `,`", Macro),
+            (None, "linking with `cc` failed: exit status: 1", Linker),
+            (None, "failed to run custom build command for `demo v0.1.0`", BuildScript),
+            (Some("E0004"), "non-exhaustive patterns: `None` not covered", Code),
+        ];
+        for (code, message, expected) in cases {
+            assert_eq!(classify_message(&error(code, message)), expected, "{code:?}: {message}");
+        }
+    }
+
+    #[test]
+    fn code_classes_have_prompt_hints() {
+        use IssueClassification::*;
+        for class in [Import, TypeMismatch, BorrowCheck, TraitBound, MethodResolution, TypeInference, Lifetime] {
+            assert!(class.is_code_fix(), "{class:?}");
+            assert!(!class.strategy_hint().is_empty(), "{class:?}");
+        }
+    }
+
+    #[test]
+    fn rank_puts_causes_before_consequences() {
+        use IssueClassification::*;
+        let order = [BuildScript, MissingDependency, MissingFeature, Import, Macro, TraitBound, MethodResolution,
+            TypeMismatch, TypeInference, Lifetime, Code, BorrowCheck, Linker, Unknown];
+        assert!(order.windows(2).all(|w| w[0].rank() < w[1].rank()), "{order:?}");
+
+        let errors = [
+            error_at(Some("E0382"), "borrow of moved value: `v`", 1),
+            error_at(Some("E0308"), "mismatched types", 2),
+            error_at(Some("E0432"), "unresolved import `crate::utils`", 9),
+        ];
+        assert_eq!(selected(&errors), "unresolved import `crate::utils`");
+        assert_eq!(selected(&errors[..2]), "mismatched types");
+    }
+
+    #[test]
+    fn groups_errors_by_root_cause() {
+        let errors = [
+            error_at(Some("E0432"), "unresolved import `serde_json::Value`", 1),
+            error_at(Some("E0412"), "cannot find type `Value` in this scope", 5),
+            error_at(Some("E0308"), "mismatched types", 7),
+            error_at(Some("E0277"), "`Value` doesn't implement `std::fmt::Display`", 7),
+        ];
+        let classes: Vec<IssueClassification> = errors.iter().map(classify_message).collect();
+        // `Value` — последствие ненайденного импорта; вторая ошибка на том же месте сводится к первой
+        assert_eq!(group_by_root_cause(&errors, &classes), vec![0, 0, 2, 2]);
+    }
+
+    #[test]
+    fn prefers_root_with_more_dependents() {
+        let errors = [
+            error_at(Some("E0433"), "failed to resolve: use of undeclared type `Config`", 9),
+            error_at(Some("E0432"), "unresolved import `crate::models::User`", 1),
+            error_at(Some("E0412"), "cannot find type `User` in this scope", 4),
+            error_at(Some("E0422"), "cannot find struct, variant or union type `User` in this scope", 6),
+        ];
+        assert_eq!(selected(&errors), "unresolved import `crate::models::User`");
+    }
+
+    #[test]
+    fn detects_follow_up_messages() {
+        let follow_ups = [
+            error(None, "aborting due to 2 previous errors"),
+            error(None, "could not compile `demo` (bin \"demo\") due to 2 previous errors"),
+            error(Some("E0282"), "type annotations needed"),
+            error(Some("E0599"), "no method named `len` found for type `{type error}`"),
+        ];
+        for message in &follow_ups {
+            assert!(is_follow_up(message), "{}", message.message);
+        }
+        for message in [error(Some("E0308"), "mismatched types"), error(None, "linking with `cc` failed: exit status: 1")] {
+            assert!(!is_follow_up(&message), "{}", message.message);
+        }
+
+        // «type annotations needed» чинится после основной ошибки, хотя по месту в файле она раньше
+        let errors = [error_at(Some("E0282"), "type annotations needed", 1), error_at(Some("E0308"), "mismatched types", 9)];
+        assert_eq!(selected(&errors), "mismatched types");
+    }
+
+    #[test]
+    fn follows_build_order() {
+        // зависимость собирается раньше: её ошибка важнее импорта в зависимом крейте
+        let dependency = in_unit(error_at(Some("E0308"), "mismatched types", 9), 0, false);
+        let dependent = in_unit(error_at(Some("E0432"), "unresolved import `crate::utils`", 1), 1, false);
+        assert_eq!(selected(&[dependent.clone(), dependency.clone()]), "mismatched types");
+
+        // упавший build.rs (без спанов, как его разбирает `build_script::parse_failures`) — раньше всего остального
+        let mut failure = error(None, "failed to run custom build command for `demo v0.1.0`");
+        failure.spans.clear();
+        let build_script = in_unit(failure, 2, true);
+        assert_eq!(selected(&[dependency, dependent, build_script]), "failed to run custom build command for `demo v0.1.0`");
+    }
+}
@@ -1,38 +1,790 @@
-use rusqlite::{Connection, Result};
+use crate::CompilerMessage;
+use super::cli::{CacheScope, ConflictPolicy};
+use super::span_patch;
+use anyhow::{anyhow, bail, Context, Result};
+use colored::*;
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fmt;
+use std::path::PathBuf;
+use std::process::Command;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
 
+/// Кэш проекта — в корне проекта.
 const DB_FILE: &str = ".rusty_fixer_cache.db";
 
-pub struct KnowledgeCache {
+/// Кэш пользователя — в `$XDG_DATA_HOME/rusty-fixer-ai/` (по умолчанию `~/.local/share`).
+const USER_DB_FILE: &str = "knowledge.db";
+const APP_DIR: &str = "rusty-fixer-ai";
+
+/// Куда переименовывается таблица старого формата (снимки целых файлов).
+const LEGACY_TABLE: &str = "solutions_full_source";
+
+/// Слой кэша: база проекта или пользователя.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheLayer {
+    Project,
+    User,
+}
+
+impl CacheLayer {
+    pub fn name(self) -> &'static str {
+        match self {
+            CacheLayer::Project => "project",
+            CacheLayer::User => "user",
+        }
+    }
+
+    pub fn path(self) -> Result<PathBuf> {
+        match self {
+            CacheLayer::Project => Ok(PathBuf::from(DB_FILE)),
+            CacheLayer::User => user_data_dir().map(|dir| dir.join(APP_DIR).join(USER_DB_FILE)),
+        }
+    }
+}
+
+fn user_data_dir() -> Result<PathBuf> {
+    let from_env = |name: &str| std::env::var_os(name).filter(|v| !v.is_empty()).map(PathBuf::from);
+    from_env("XDG_DATA_HOME")
+        .or_else(|| from_env("HOME").map(|home| home.join(".local/share")))
+        .or_else(|| from_env("APPDATA"))
+        .ok_or_else(|| anyhow!("Cannot locate the user data directory: set XDG_DATA_HOME or HOME"))
+}
+
+/// Ссылка на запись для команд `cache`: `12` или, при нескольких слоях, `user:12` / `project:12`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntryRef {
+    pub layer: Option<CacheLayer>,
+    pub id: i64,
+}
+
+impl FromStr for EntryRef {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (layer, id) = match s.split_once(':') {
+            Some(("project", id)) => (Some(CacheLayer::Project), id),
+            Some(("user", id)) => (Some(CacheLayer::User), id),
+            Some((other, _)) => return Err(format!("unknown cache layer `{other}` (expected project or user)")),
+            None => (None, s),
+        };
+        let id = id.parse().map_err(|_| format!("`{id}` is not an entry id"))?;
+        Ok(Self { layer, id })
+    }
+}
+
+/// Сохранённое решение: минимальный патч (блоки SEARCH/REPLACE с контекстом, как их пишет
+/// `span_patch::format_blocks`) и условия, в которых он был найден.
+/// В JSONL-экспорт попадает всё, кроме места хранения (`id`, `layer`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedFix {
+    /// rowid записи — по нему к ней обращаются команды `cache show|delete`.
+    #[serde(skip)]
+    pub id: i64,
+    #[serde(skip, default = "default_layer")]
+    pub layer: CacheLayer,
+    pub signature: String,
+    pub patch: String,
+    /// `rustc --version` на момент сохранения.
+    #[serde(default)]
+    pub toolchain: String,
+    /// Прямые зависимости пакета с разрешёнными версиями: `serde 1.0.210, tokio 1.40.0`.
+    #[serde(default)]
+    pub crate_versions: String,
+    #[serde(default)]
+    pub model: String,
+    /// Unix-время сохранения.
+    #[serde(default)]
+    pub created_at: i64,
+    /// Сколько раз решение находилось по сигнатуре.
+    #[serde(default)]
+    pub hits: u32,
+    /// Сколько раз патч прошёл проверку и был применён (включая исходное сохранение).
+    #[serde(default)]
+    pub successes: u32,
+    /// Сколько раз патч не лёг на файл или не прошёл проверку.
+    #[serde(default)]
+    pub failures: u32,
+}
+
+fn default_layer() -> CacheLayer {
+    CacheLayer::Project
+}
+
+impl CachedFix {
+    /// Код ошибки из сигнатуры (`E0599`, `-` для диагностик без кода).
+    pub fn error_code(&self) -> &str {
+        self.signature.split('|').next().unwrap_or_default()
+    }
+
+    /// Доля успешных применений среди всех попыток.
+    pub fn success_rate(&self) -> f64 {
+        let attempts = self.successes + self.failures;
+        if attempts == 0 {
+            return 0.0;
+        }
+        f64::from(self.successes) / f64::from(attempts)
+    }
+
+    pub fn age_secs(&self) -> i64 {
+        unix_now() - self.created_at
+    }
+
+    /// Надёжность решения: выше доля успехов, при равенстве — больше попыток, затем новее.
+    fn reliability_cmp(&self, other: &CachedFix) -> Ordering {
+        self.success_rate()
+            .total_cmp(&other.success_rate())
+            .then((self.successes + self.failures).cmp(&(other.successes + other.failures)))
+            .then(self.created_at.cmp(&other.created_at))
+    }
+
+    /// Решает конфликт импорта: заменить ли существующую запись `self` на `incoming`.
+    fn replaced_by(&self, incoming: &CachedFix, policy: ConflictPolicy) -> bool {
+        match policy {
+            ConflictPolicy::Keep => false,
+            ConflictPolicy::Replace => true,
+            ConflictPolicy::Newer => incoming.created_at > self.created_at,
+            ConflictPolicy::Better => incoming.reliability_cmp(self).is_gt(),
+        }
+    }
+}
+
+/// Итог импорта по всем открытым слоям.
+#[derive(Debug, Default)]
+pub struct ImportStats {
+    pub added: usize,
+    pub replaced: usize,
+    pub kept: usize,
+}
+
+/// Одна запись на пару (сигнатура, патч): разные проверенные решения одной ошибки
+/// хранятся рядом со своими счётчиками, а не затирают друг друга.
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS solutions(
+    signature TEXT NOT NULL,
+    patch TEXT NOT NULL,
+    toolchain TEXT NOT NULL,
+    crate_versions TEXT NOT NULL,
+    model TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    hits INTEGER NOT NULL DEFAULT 0,
+    successes INTEGER NOT NULL DEFAULT 0,
+    failures INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (signature, patch)
+);";
+
+const INSERT_FIX: &str = "INTO solutions(signature, patch, toolchain, crate_versions, model, created_at, hits, successes, failures)
+     VALUES(?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)";
+
+const COLUMNS: &str = "rowid, signature, patch, toolchain, crate_versions, model, created_at, hits, successes, failures";
+
+fn fix_from_row(layer: CacheLayer) -> impl Fn(&rusqlite::Row) -> rusqlite::Result<CachedFix> {
+    move |row| {
+        Ok(CachedFix {
+            id: row.get(0)?,
+            layer,
+            signature: row.get(1)?,
+            patch: row.get(2)?,
+            toolchain: row.get(3)?,
+            crate_versions: row.get(4)?,
+            model: row.get(5)?,
+            created_at: row.get(6)?,
+            hits: row.get(7)?,
+            successes: row.get(8)?,
+            failures: row.get(9)?,
+        })
+    }
+}
+
+struct Layer {
+    kind: CacheLayer,
+    path: PathBuf,
     conn: Connection,
 }
 
-impl KnowledgeCache {
-    pub fn new() -> Result<Self> {
-        let conn = Connection::open(DB_FILE)?;
-        conn.execute_batch(
-            "CREATE TABLE IF NOT EXISTS solutions(
-                signature TEXT PRIMARY KEY,
-                full_source TEXT NOT NULL
-            );",
-        )?;
-        Ok(Self { conn })
+impl Layer {
+    fn open(kind: CacheLayer) -> Result<Self> {
+        let path = kind.path()?;
+        if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let conn = Connection::open(&path).with_context(|| format!("Failed to open {}", path.display()))?;
+        Self::with_connection(kind, path, conn)
     }
 
-    pub fn lookup(&self, signature: &str) -> Result<Option<String>> {
-        let mut stmt = self.conn.prepare("SELECT full_source FROM solutions WHERE signature=?1")?;
-        let mut rows = stmt.query([signature])?;
-        if let Some(row) = rows.next()? {
-            let code: String = row.get(0)?;
-            return Ok(Some(code));
+    /// Приводит схему открытой базы к текущей.
+    fn with_connection(kind: CacheLayer, path: PathBuf, conn: Connection) -> Result<Self> {
+        // Прежняя схема хранила снимки целых файлов: без исходного текста патч из них
+        // не восстановить, а воспроизводить их поверх новых правок нельзя. Таблицу не
+        // удаляем, а переименовываем — данные остаются в файле для ручного разбора.
+        if conn.prepare("SELECT full_source FROM solutions LIMIT 0").is_ok() {
+            let rows: i64 = conn.query_row("SELECT COUNT(*) FROM solutions", [], |row| row.get(0))?;
+            conn.execute_batch(&format!("ALTER TABLE solutions RENAME TO {LEGACY_TABLE};"))
+                .with_context(|| format!("Failed to back up the old cache table in {}", path.display()))?;
+            eprintln!(
+                "{} {}: {rows} whole-file entries use the old format and cannot be replayed; moved them to table `{LEGACY_TABLE}`.",
+                "Knowledge cache".yellow().bold(),
+                path.display()
+            );
+        }
+        // Раньше ключом была одна сигнатура; записи переносятся как есть, с теми же rowid
+        let table_sql: Option<String> = conn
+            .query_row("SELECT sql FROM sqlite_master WHERE type='table' AND name='solutions'", [], |row| row.get(0))
+            .optional()?;
+        if table_sql.is_some_and(|sql| sql.contains("signature TEXT PRIMARY KEY")) {
+            conn.execute_batch(&format!(
+                "BEGIN;
+                 ALTER TABLE solutions RENAME TO solutions_by_signature;
+                 {SCHEMA}
+                 INSERT INTO solutions({COLUMNS}) SELECT {COLUMNS} FROM solutions_by_signature;
+                 DROP TABLE solutions_by_signature;
+                 COMMIT;"
+            ))
+            .with_context(|| format!("Failed to migrate the cache table in {}", path.display()))?;
         }
-        Ok(None)
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self { kind, path, conn })
+    }
+
+    /// Все решения с этой сигнатурой.
+    fn find_all(&self, signature: &str) -> Result<Vec<CachedFix>> {
+        let mut stmt = self.conn.prepare(&format!("SELECT {COLUMNS} FROM solutions WHERE signature=?1"))?;
+        let rows = stmt.query_map([signature], fix_from_row(self.kind))?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
     }
 
-    pub fn store(&self, signature: &str, code: &str) -> Result<()> {
+    fn find(&self, signature: &str, patch: &str) -> Result<Option<CachedFix>> {
+        Ok(self
+            .conn
+            .query_row(
+                &format!("SELECT {COLUMNS} FROM solutions WHERE signature=?1 AND patch=?2"),
+                [signature, patch],
+                fix_from_row(self.kind),
+            )
+            .optional()?)
+    }
+
+    /// Записывает решение; запись с той же сигнатурой и тем же патчем заменяется.
+    fn insert(&self, fix: &CachedFix) -> Result<()> {
+        self.write(&format!("INSERT OR REPLACE {INSERT_FIX}"), fix)
+    }
+
+    /// Новое решение или ещё один успех уже известного патча (с обновлёнными условиями).
+    fn store(&self, fix: &CachedFix) -> Result<()> {
+        self.write(
+            &format!(
+                "INSERT {INSERT_FIX} ON CONFLICT(signature, patch) DO UPDATE SET successes = successes + 1,
+                 toolchain = excluded.toolchain, crate_versions = excluded.crate_versions, model = excluded.model"
+            ),
+            fix,
+        )
+    }
+
+    fn write(&self, sql: &str, fix: &CachedFix) -> Result<()> {
         self.conn.execute(
-            "INSERT OR REPLACE INTO solutions(signature, full_source) VALUES(?1, ?2)",
-            (signature, code),
+            sql,
+            (
+                &fix.signature,
+                &fix.patch,
+                &fix.toolchain,
+                &fix.crate_versions,
+                &fix.model,
+                fix.created_at,
+                fix.hits,
+                fix.successes,
+                fix.failures,
+            ),
         )?;
         Ok(())
     }
 }
+
+/// Кэш решений из одного или двух слоёв. Поиск идёт по слоям по порядку (сначала проект),
+/// новые решения и импорт записываются во все открытые слои.
+pub struct KnowledgeCache {
+    layers: Vec<Layer>,
+}
+
+impl KnowledgeCache {
+    pub fn new(scope: CacheScope) -> Result<Self> {
+        let kinds: &[CacheLayer] = match scope {
+            CacheScope::Project => &[CacheLayer::Project],
+            CacheScope::User => &[CacheLayer::User],
+            CacheScope::Layered => &[CacheLayer::Project, CacheLayer::User],
+        };
+        let layers = kinds.iter().map(|&kind| Layer::open(kind)).collect::<Result<_>>()?;
+        Ok(Self { layers })
+    }
+
+    pub fn is_layered(&self) -> bool {
+        self.layers.len() > 1
+    }
+
+    /// Открытые слои и пути к их файлам.
+    pub fn locations(&self) -> Vec<(CacheLayer, &PathBuf)> {
+        self.layers.iter().map(|l| (l.kind, &l.path)).collect()
+    }
+
+    /// Все решения по сигнатуре, самые надёжные первыми; патч из нескольких слоёв берётся
+    /// из первого. Каждое найденное засчитывается как попадание.
+    pub fn lookup(&self, signature: &str) -> Result<Vec<CachedFix>> {
+        let mut seen = HashSet::new();
+        let mut found = Vec::new();
+        for layer in &self.layers {
+            for fix in layer.find_all(signature)? {
+                if seen.insert(fix.patch.clone()) {
+                    layer.conn.execute("UPDATE solutions SET hits = hits + 1 WHERE rowid=?1", [fix.id])?;
+                    found.push(fix);
+                }
+            }
+        }
+        found.sort_by(|a, b| b.reliability_cmp(a));
+        Ok(found)
+    }
+
+    /// Сохраняет проверенный патч. Другие решения той же сигнатуры остаются со своими
+    /// счётчиками; повторное сохранение того же патча засчитывается как успех.
+    pub fn store(&self, signature: &str, patch: &str, crate_versions: &str, model: &str) -> Result<()> {
+        let fix = CachedFix {
+            id: 0,
+            layer: CacheLayer::Project,
+            signature: signature.to_string(),
+            patch: patch.to_string(),
+            toolchain: toolchain_version(),
+            crate_versions: crate_versions.to_string(),
+            model: model.to_string(),
+            created_at: unix_now(),
+            hits: 0,
+            successes: 1,
+            failures: 0,
+        };
+        self.layers.iter().try_for_each(|layer| layer.store(&fix))
+    }
+
+    /// Итог повторного применения патча из кэша (во всех слоях, где он есть).
+    pub fn record_outcome(&self, fix: &CachedFix, success: bool) -> Result<()> {
+        let column = if success { "successes" } else { "failures" };
+        for layer in &self.layers {
+            layer.conn.execute(
+                &format!("UPDATE solutions SET {column} = {column} + 1 WHERE signature=?1 AND patch=?2"),
+                [&fix.signature, &fix.patch],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Все записи всех слоёв, в порядке сохранения.
+    pub fn entries(&self) -> Result<Vec<CachedFix>> {
+        let mut all = Vec::new();
+        for layer in &self.layers {
+            let mut stmt = layer.conn.prepare(&format!("SELECT {COLUMNS} FROM solutions ORDER BY created_at"))?;
+            let rows = stmt.query_map([], fix_from_row(layer.kind))?;
+            all.extend(rows.collect::<rusqlite::Result<Vec<_>>>()?);
+        }
+        all.sort_by_key(|f| f.created_at);
+        Ok(all)
+    }
+
+    /// Записи для экспорта: по одной на пару (сигнатура, патч), при совпадении побеждает слой проекта.
+    pub fn export(&self) -> Result<Vec<CachedFix>> {
+        let mut seen = HashSet::new();
+        let mut out = Vec::new();
+        for layer in &self.layers {
+            let mut stmt = layer.conn.prepare(&format!("SELECT {COLUMNS} FROM solutions ORDER BY signature, patch"))?;
+            for fix in stmt.query_map([], fix_from_row(layer.kind))? {
+                let fix = fix?;
+                if seen.insert((fix.signature.clone(), fix.patch.clone())) {
+                    out.push(fix);
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    /// Импортирует записи; конфликт с тем же патчем для той же сигнатуры решает `policy`,
+    /// другие патчи добавляются рядом. Пишет в один слой:
+    /// в `layered` — в кэш пользователя, общий для проектов, поэтому каждая запись
+    /// учитывается в статистике ровно один раз.
+    pub fn import(&self, fixes: &[CachedFix], policy: ConflictPolicy) -> Result<ImportStats> {
+        let layer = self.import_target();
+        let mut stats = ImportStats::default();
+        let tx = layer.conn.unchecked_transaction()?;
+        for fix in fixes {
+            match layer.find(&fix.signature, &fix.patch)? {
+                None => stats.added += 1,
+                Some(existing) if existing.replaced_by(fix, policy) => stats.replaced += 1,
+                Some(_) => {
+                    stats.kept += 1;
+                    continue;
+                }
+            }
+            layer.insert(fix)?;
+        }
+        tx.commit()?;
+        Ok(stats)
+    }
+
+    /// Слой, в который загружается импорт.
+    fn import_target(&self) -> &Layer {
+        self.layers.iter().find(|l| l.kind == CacheLayer::User).unwrap_or(&self.layers[0])
+    }
+
+    pub fn entry(&self, entry: EntryRef) -> Result<Option<CachedFix>> {
+        let layer = self.layer_for(entry)?;
+        Ok(layer
+            .conn
+            .query_row(&format!("SELECT {COLUMNS} FROM solutions WHERE rowid=?1"), [entry.id], fix_from_row(layer.kind))
+            .optional()?)
+    }
+
+    /// Удаляет записи; возвращает, сколько было удалено.
+    pub fn delete(&self, entries: &[EntryRef]) -> Result<usize> {
+        let mut removed = 0;
+        for &entry in entries {
+            removed += self.layer_for(entry)?.conn.execute("DELETE FROM solutions WHERE rowid=?1", [entry.id])?;
+        }
+        Ok(removed)
+    }
+
+    /// Сжимает файлы БД после удалений.
+    pub fn vacuum(&self) -> Result<()> {
+        self.layers.iter().try_for_each(|layer| Ok(layer.conn.execute_batch("VACUUM;")?))
+    }
+
+    /// Ссылка на запись в том виде, в каком её принимают `cache show|delete`.
+    pub fn label(&self, fix: &CachedFix) -> String {
+        if self.is_layered() { format!("{}:{}", fix.layer.name(), fix.id) } else { fix.id.to_string() }
+    }
+
+    fn layer_for(&self, entry: EntryRef) -> Result<&Layer> {
+        match entry.layer {
+            Some(kind) => self.layers.iter().find(|l| l.kind == kind).ok_or_else(|| {
+                anyhow!("The {} cache is not open; pass --cache-scope {} or layered", kind.name(), kind.name())
+            }),
+            None if self.is_layered() => bail!("Entry id {} is ambiguous: prefix it with project: or user:", entry.id),
+            None => Ok(&self.layers[0]),
+        }
+    }
+}
+
+/// Прошлое решение, похожее на текущую ошибку, и его оценка (0..1).
+#[derive(Debug, Clone)]
+pub struct SimilarFix {
+    pub score: f64,
+    pub fix: CachedFix,
+}
+
+/// Сколько похожих решений показывать модели как примеры.
+pub const FEW_SHOT_EXAMPLES: usize = 3;
+
+/// Ниже этой оценки решение считается непохожим: у ошибок без кода
+/// нужен почти тот же текст сообщения.
+const MIN_SIMILARITY: f64 = 0.35;
+
+/// Веса признаков: совпадение кода ошибки, похожесть нормализованного сообщения
+/// (триграммы) и похожесть кода вокруг ошибки (общие идентификаторы).
+const CODE_WEIGHT: f64 = 0.35;
+const MESSAGE_WEIGHT: f64 = 0.45;
+const SOURCE_WEIGHT: f64 = 0.2;
+
+impl KnowledgeCache {
+    /// Нечёткий поиск: решения с тем же кодом ошибки, похожим сообщением и похожим кодом вокруг,
+    /// лучшие `k` по убыванию оценки. Ненадёжные решения (много неудачных применений) понижаются.
+    /// Попаданием не считается.
+    pub fn similar(&self, signature: &str, source: &str, k: usize) -> Result<Vec<SimilarFix>> {
+        let (code, message) = split_signature(signature);
+        let message = trigrams(message);
+        let source = identifiers(source);
+
+        let mut found: Vec<SimilarFix> = self
+            .with_error_code(code)?
+            .into_iter()
+            .filter_map(|fix| {
+                let (fix_code, fix_message) = split_signature(&fix.signature);
+                let same_code = code != "-" && code == fix_code;
+                let before: String = span_patch::parse_blocks(&fix.patch)
+                    .iter()
+                    .filter(|h| h.file.is_none())
+                    .flat_map(|h| h.search.iter().map(String::as_str))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                let relevance = CODE_WEIGHT * f64::from(u8::from(same_code))
+                    + MESSAGE_WEIGHT * jaccard(&message, &trigrams(fix_message))
+                    + SOURCE_WEIGHT * jaccard(&source, &identifiers(&before));
+                let score = relevance * (0.5 + 0.5 * fix.success_rate());
+                (score >= MIN_SIMILARITY).then_some(SimilarFix { score, fix })
+            })
+            .collect();
+        found.sort_by(|a, b| b.score.total_cmp(&a.score));
+        found.truncate(k);
+        Ok(found)
+    }
+
+    /// Кандидаты в похожие: только записи с тем же кодом ошибки, по одной на пару (сигнатура, патч).
+    /// Отбор идёт диапазоном по первичному ключу, так что патчи остального кэша не читаются.
+    fn with_error_code(&self, code: &str) -> Result<Vec<CachedFix>> {
+        // '}' следует за '|' в ASCII: диапазон [code|, code}) — ровно сигнатуры с этим кодом
+        let (from, to) = (format!("{code}|"), format!("{code}}}"));
+        let mut seen = HashSet::new();
+        let mut out = Vec::new();
+        for layer in &self.layers {
+            let mut stmt = layer
+                .conn
+                .prepare(&format!("SELECT {COLUMNS} FROM solutions WHERE signature >= ?1 AND signature < ?2"))?;
+            for fix in stmt.query_map([&from, &to], fix_from_row(layer.kind))? {
+                let fix = fix?;
+                if seen.insert((fix.signature.clone(), fix.patch.clone())) {
+                    out.push(fix);
+                }
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// Похожие решения в виде примеров для промпта: ошибка, надёжность и патч.
+pub fn describe_examples(similar: &[SimilarFix]) -> String {
+    let mut out = String::new();
+    for (n, found) in similar.iter().enumerate() {
+        let fix = &found.fix;
+        let (code, message) = split_signature(&fix.signature);
+        out.push_str(&format!(
+            "Example {} (error {code}: {message}; applied successfully {} of {} times):\n{}\n",
+            n + 1,
+            fix.successes,
+            fix.successes + fix.failures,
+            fix.patch.trim_end()
+        ));
+    }
+    out
+}
+
+/// Сигнатура `код|сообщение` → (код, сообщение).
+fn split_signature(signature: &str) -> (&str, &str) {
+    signature.split_once('|').unwrap_or(("-", signature))
+}
+
+fn trigrams(text: &str) -> HashSet<String> {
+    let chars: Vec<char> = format!("  {} ", text.to_lowercase()).chars().collect();
+    chars.windows(3).map(|w| w.iter().collect()).collect()
+}
+
+fn identifiers(code: &str) -> HashSet<String> {
+    code.split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .filter(|t| t.len() > 1 && !t.starts_with(|c: char| c.is_ascii_digit()))
+        .map(str::to_string)
+        .collect()
+}
+
+fn jaccard(a: &HashSet<String>, b: &HashSet<String>) -> f64 {
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+    a.intersection(b).count() as f64 / a.union(b).count() as f64
+}
+
+impl fmt::Display for CacheLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+fn toolchain_version() -> String {
+    Command::new("rustc")
+        .arg("--version")
+        .output()
+        .ok()
+        .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+        .unwrap_or_default()
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() as i64)
+}
+
+/// Примитивные типы: в сигнатуре тоже заменяются плейсхолдерами (`{P1}`), чтобы
+/// `expected `u32`, found `i64`` и `expected `u8`, found `usize`` были одной ошибкой.
+const PRIMITIVES: &[&str] = &[
+    "bool", "char", "str", "i8", "i16", "i32", "i64", "i128", "isize", "u8", "u16", "u32", "u64", "u128", "usize", "f32", "f64",
+];
+
+/// Слова, которые не зависят от проекта: ключевые слова, типы, трейты и модули std.
+/// Остальные идентификаторы в сигнатуре заменяются плейсхолдерами.
+const VOCABULARY: &[&str] = &[
+    "as", "async", "await", "const", "crate", "dyn", "fn", "for", "impl", "in", "let", "move", "mut", "pub", "ref", "self", "Self",
+    "static", "super", "unsafe", "where", "integer", "float",
+    "std", "core", "alloc", "collections", "fmt", "io", "sync", "fs", "path", "string", "vec", "ops", "iter", "result", "option",
+    "String", "Vec", "Option", "Result", "Box", "Rc", "Arc", "RefCell", "Cell", "Mutex", "RwLock", "HashMap", "HashSet",
+    "BTreeMap", "BTreeSet", "VecDeque", "Cow", "Path", "PathBuf", "Some", "None", "Ok", "Err",
+    "Clone", "Copy", "Debug", "Display", "Default", "PartialEq", "Eq", "PartialOrd", "Ord", "Hash", "Send", "Sync", "Sized",
+    "Iterator", "IntoIterator", "From", "Into", "TryFrom", "TryInto", "AsRef", "Deref", "Drop", "Fn", "FnMut", "FnOnce",
+    "Future", "Error", "ToString", "Serialize", "Deserialize",
+];
+
+/// Сигнатура диагностики для кэша, не зависящая от проекта: код ошибки и «форма» сообщения.
+/// Идентификаторы пользователя становятся `{T1}`, `{v1}`, примитивные типы — `{P1}` (нумерация
+/// по первому появлению, чтобы `expected `Foo`, found `Foo`` и `expected `Foo`, found `Bar``
+/// различались), литералы — `{lit}`, числа и номера строк — `{N}`, пути к файлам — `{path}`. Так решение из одного крейта находится в другом.
+pub fn error_signature(message: &CompilerMessage) -> String {
+    let code = message.code.as_ref().map_or("-", |c| c.code.as_str());
+    // Сообщение без кода в кавычках (`mismatched types`) одно на все случаи — уточняем его
+    // меткой основного спана или первой подсказкой help/note, иначе разные ошибки затрут
+    // друг другу решение.
+    let mut text = message.message.clone();
+    if !text.contains('`') {
+        let detail = message
+            .primary_span()
+            .and_then(|s| s.label.as_deref())
+            .or_else(|| message.children.first().map(|c| c.message.as_str()))
+            .filter(|d| !d.is_empty());
+        if let Some(detail) = detail {
+            text = format!("{text}: {detail}");
+        }
+    }
+    format!("{code}|{}", normalize_message(&text))
+}
+
+pub fn normalize_message(text: &str) -> String {
+    let mut names: Vec<String> = Vec::new();
+    let mut out = String::new();
+    for (i, part) in text.split('`').enumerate() {
+        if i % 2 == 1 {
+            out.push('`');
+            out.push_str(&normalize_code(part, &mut names));
+            out.push('`');
+        } else {
+            out.push_str(&normalize_prose(part));
+        }
+    }
+    out
+}
+
+/// Код в обратных кавычках: идентификаторы вне словаря, примитивы, литералы и числа — в плейсхолдеры.
+fn normalize_code(code: &str, names: &mut Vec<String>) -> String {
+    let mut out = String::new();
+    let mut token = String::new();
+    let flush = |token: &mut String, out: &mut String, names: &mut Vec<String>| {
+        if token.is_empty() {
+            return;
+        }
+        if token.starts_with(|c: char| c.is_ascii_digit()) {
+            out.push_str("{N}");
+        } else if VOCABULARY.contains(&token.as_str()) {
+            out.push_str(token);
+        } else {
+            let index = match names.iter().position(|n| n == token) {
+                Some(index) => index,
+                None => {
+                    names.push(token.clone());
+                    names.len() - 1
+                }
+            };
+            let kind = if PRIMITIVES.contains(&token.as_str()) {
+                "P"
+            } else if token.starts_with(char::is_uppercase) {
+                "T"
+            } else {
+                "v"
+            };
+            out.push_str(&format!("{{{kind}{}}}", index + 1));
+        }
+        token.clear();
+    };
+    let mut chars = code.chars();
+    while let Some(ch) = chars.next() {
+        if ch.is_alphanumeric() || ch == '_' {
+            token.push(ch);
+        } else if ch == '"' {
+            flush(&mut token, &mut out, names);
+            // строковый литерал целиком, с учётом экранированных кавычек
+            let mut escaped = false;
+            for c in chars.by_ref() {
+                if c == '"' && !escaped {
+                    break;
+                }
+                escaped = c == '\\' && !escaped;
+            }
+            out.push_str("{lit}");
+        } else {
+            flush(&mut token, &mut out, names);
+            out.push(ch);
+        }
+    }
+    flush(&mut token, &mut out, names);
+    out
+}
+
+/// Текст сообщения вне кода: пути к файлам и числа (строки, количества аргументов) — в плейсхолдеры.
+fn normalize_prose(text: &str) -> String {
+    text.split(' ')
+        .map(|word| {
+            let bare = word.trim_end_matches([',', '.', ':', ';', ')']);
+            let tail = &word[bare.len()..];
+            if bare.contains('/') || bare.contains('\\') || bare.ends_with(".rs") {
+                format!("{{path}}{tail}")
+            } else if !bare.is_empty() && bare.chars().all(|c| c.is_ascii_digit()) {
+                format!("{{N}}{tail}")
+            } else {
+                word.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn in_memory() -> KnowledgeCache {
+        let conn = Connection::open_in_memory().unwrap();
+        let layer = Layer::with_connection(CacheLayer::Project, PathBuf::from(":memory:"), conn).unwrap();
+        KnowledgeCache { layers: vec![layer] }
+    }
+
+    const SIGNATURE: &str = "E0308|mismatched types";
+
+    #[test]
+    fn keeps_every_patch_for_a_signature() {
+        let cache = in_memory();
+        cache.store(SIGNATURE, "first", "", "model").unwrap();
+        cache.store(SIGNATURE, "second", "", "model").unwrap();
+        cache.store(SIGNATURE, "second", "", "model").unwrap();
+
+        let found = cache.lookup(SIGNATURE).unwrap();
+        let patches: Vec<&str> = found.iter().map(|f| f.patch.as_str()).collect();
+        assert_eq!(patches, ["second", "first"], "more successful patch first");
+        assert_eq!(found[0].successes, 2);
+
+        // неудачи понижают решение, но не удаляют его
+        cache.record_outcome(&found[0], false).unwrap();
+        cache.record_outcome(&found[0], false).unwrap();
+        let found = cache.lookup(SIGNATURE).unwrap();
+        assert_eq!(found.iter().map(|f| f.patch.as_str()).collect::<Vec<_>>(), ["first", "second"]);
+        assert!(found.iter().all(|f| f.hits == 1), "{found:?}");
+    }
+
+    #[test]
+    fn migrates_signature_keyed_table() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE solutions(
+                signature TEXT PRIMARY KEY, patch TEXT NOT NULL, toolchain TEXT NOT NULL, crate_versions TEXT NOT NULL,
+                model TEXT NOT NULL, created_at INTEGER NOT NULL, hits INTEGER NOT NULL DEFAULT 0,
+                successes INTEGER NOT NULL DEFAULT 0, failures INTEGER NOT NULL DEFAULT 0
+            );
+            INSERT INTO solutions VALUES('E0308|mismatched types', 'old', '', '', 'model', 1, 4, 3, 1);",
+        )
+        .unwrap();
+        let layer = Layer::with_connection(CacheLayer::Project, PathBuf::from(":memory:"), conn).unwrap();
+        let cache = KnowledgeCache { layers: vec![layer] };
+
+        cache.store(SIGNATURE, "new", "", "model").unwrap();
+        let found = cache.lookup(SIGNATURE).unwrap();
+        assert_eq!(found.len(), 2);
+        let old = found.iter().find(|f| f.patch == "old").unwrap();
+        assert_eq!((old.hits, old.successes, old.failures), (4, 3, 1));
+    }
+}
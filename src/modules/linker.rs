@@ -0,0 +1,562 @@
+use crate::{run_cargo_in, CompilerMessage};
+use super::approval::Review;
+use super::build_script;
+use super::cargo_expert::merge_features;
+use super::change_set::{ChangeSet, PatchSet};
+use super::patch_engine::ErrorCheck;
+use super::workspace::{ScratchCopy, Workspace};
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use toml_edit::{Array, DocumentMut, InlineTable, Item, Value};
+
+/// Что удалось распознать в выводе линкера.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LinkerProblem {
+    /// `-lfoo` не найдена: нет библиотеки или она не в путях поиска.
+    MissingLibrary(String),
+    /// pkg-config не знает пакета (обычно не установлен `-dev`-пакет).
+    MissingPkgConfig(String),
+    UndefinedSymbols(Vec<String>),
+    DuplicateSymbols(Vec<String>),
+}
+
+impl LinkerProblem {
+    fn describe(&self) -> String {
+        match self {
+            LinkerProblem::MissingLibrary(lib) => format!("native library `{lib}` (-l{lib}) was not found"),
+            LinkerProblem::MissingPkgConfig(name) => format!("pkg-config package `{name}` was not found"),
+            LinkerProblem::UndefinedSymbols(syms) => format!("undefined symbols: {}", list(syms)),
+            LinkerProblem::DuplicateSymbols(syms) => format!("duplicate symbols: {}", list(syms)),
+        }
+    }
+}
+
+/// Предлагаемое исправление: автоматическая правка или совет пользователю.
+#[derive(Debug, Clone)]
+enum LinkerFix {
+    /// Собрать библиотеку из исходников через фичу `*-sys` крейта (`vendored`, `bundled`, ...).
+    EnableFeature { krate: String, version: String, feature: String },
+    /// `cargo:rustc-link-lib=<lib>` в build.rs пакета.
+    LinkLib(String),
+    Advice(String),
+}
+
+impl LinkerFix {
+    fn describe(&self) -> String {
+        match self {
+            LinkerFix::EnableFeature { krate, feature, .. } => format!("enable feature `{feature}` of `{krate}`"),
+            LinkerFix::LinkLib(lib) => format!("link `{lib}` from build.rs (cargo:rustc-link-lib={lib})"),
+            LinkerFix::Advice(text) => text.clone(),
+        }
+    }
+}
+
+/// Известная нативная библиотека: как её зовут линкер и pkg-config, какой `*-sys` крейт
+/// умеет собрать её сам, чьи это символы и в каких системных пакетах она лежит.
+struct NativeLib {
+    names: &'static [&'static str],
+    sys_crate: Option<(&'static str, &'static str)>,
+    /// Символы библиотеки: с `_` на конце — префикс её пространства имён (`sqlite3_`),
+    /// остальные — точные имена, чтобы `inflate` или `FcInit` не цепляли чужие символы.
+    symbols: &'static [&'static str],
+    /// Заголовки C, по которым build-скрипты узнают об отсутствии библиотеки.
+    headers: &'static [&'static str],
+    packages: &'static str,
+}
+
+const NATIVE_LIBS: &[NativeLib] = &[
+    NativeLib {
+        names: &["ssl", "crypto", "openssl", "libssl", "libcrypto"],
+        sys_crate: Some(("openssl-sys", "vendored")),
+        symbols: &["SSL_", "SSL_CTX_", "EVP_", "OPENSSL_", "X509_", "BIO_", "CRYPTO_", "ERR_get_error", "ERR_error_string", "ERR_clear_error"],
+        headers: &["openssl/"],
+        packages: "libssl-dev (Debian/Ubuntu), openssl-devel (Fedora), openssl (Homebrew)",
+    },
+    NativeLib {
+        names: &["sqlite3", "sqlite"],
+        sys_crate: Some(("libsqlite3-sys", "bundled")),
+        symbols: &["sqlite3_"],
+        headers: &["sqlite3.h"],
+        packages: "libsqlite3-dev (Debian/Ubuntu), sqlite-devel (Fedora), sqlite (Homebrew)",
+    },
+    NativeLib {
+        names: &["z", "zlib"],
+        sys_crate: Some(("libz-sys", "static")),
+        symbols: &[
+            "deflate", "deflateInit_", "deflateInit2_", "deflateEnd", "inflate", "inflateInit_", "inflateInit2_", "inflateEnd",
+            "compress", "compress2", "uncompress", "crc32", "adler32", "zlibVersion", "gzopen", "gzread", "gzwrite", "gzclose",
+        ],
+        headers: &["zlib.h"],
+        packages: "zlib1g-dev (Debian/Ubuntu), zlib-devel (Fedora), zlib (Homebrew)",
+    },
+    NativeLib {
+        names: &["git2", "libgit2"],
+        sys_crate: Some(("libgit2-sys", "vendored")),
+        symbols: &["git_"],
+        headers: &["git2.h"],
+        packages: "libgit2-dev (Debian/Ubuntu), libgit2-devel (Fedora), libgit2 (Homebrew)",
+    },
+    NativeLib {
+        names: &["curl", "libcurl"],
+        sys_crate: Some(("curl-sys", "static-curl")),
+        symbols: &["curl_"],
+        headers: &["curl/curl.h"],
+        packages: "libcurl4-openssl-dev (Debian/Ubuntu), libcurl-devel (Fedora), curl (Homebrew)",
+    },
+    NativeLib {
+        names: &["pq", "libpq"],
+        sys_crate: Some(("pq-sys", "bundled")),
+        symbols: &["PQconnectdb", "PQconnectdbParams", "PQfinish", "PQstatus", "PQexec", "PQexecParams", "PQerrorMessage", "PQclear", "PQgetvalue", "lo_open", "lo_creat", "lo_import", "lo_export"],
+        headers: &["libpq-fe.h"],
+        packages: "libpq-dev (Debian/Ubuntu), libpq-devel (Fedora), libpq (Homebrew)",
+    },
+    NativeLib {
+        names: &["lzma", "liblzma"],
+        sys_crate: Some(("lzma-sys", "static")),
+        symbols: &["lzma_"],
+        headers: &["lzma.h"],
+        packages: "liblzma-dev (Debian/Ubuntu), xz-devel (Fedora), xz (Homebrew)",
+    },
+    NativeLib {
+        names: &["dbus-1", "dbus"],
+        sys_crate: Some(("libdbus-sys", "vendored")),
+        symbols: &["dbus_"],
+        headers: &["dbus/dbus.h"],
+        packages: "libdbus-1-dev (Debian/Ubuntu), dbus-devel (Fedora)",
+    },
+    NativeLib {
+        names: &["asound", "alsa"],
+        sys_crate: None,
+        symbols: &["snd_"],
+        headers: &["alsa/"],
+        packages: "libasound2-dev (Debian/Ubuntu), alsa-lib-devel (Fedora)",
+    },
+    NativeLib {
+        names: &["udev", "libudev"],
+        sys_crate: None,
+        symbols: &["udev_"],
+        headers: &["libudev.h"],
+        packages: "libudev-dev (Debian/Ubuntu), systemd-devel (Fedora)",
+    },
+    NativeLib {
+        names: &["X11", "x11"],
+        sys_crate: None,
+        symbols: &["XOpenDisplay", "XCloseDisplay", "XCreateWindow", "XCreateSimpleWindow", "XMapWindow", "XNextEvent", "XFree"],
+        headers: &["X11/"],
+        packages: "libx11-dev (Debian/Ubuntu), libX11-devel (Fedora)",
+    },
+    NativeLib {
+        names: &["fontconfig"],
+        sys_crate: None,
+        symbols: &["FcInit", "FcFini", "FcConfigGetCurrent", "FcConfigSubstitute", "FcDefaultSubstitute", "FcFontMatch", "FcFontList", "FcNameParse", "FcPatternCreate", "FcPatternDestroy"],
+        headers: &["fontconfig/"],
+        packages: "libfontconfig1-dev (Debian/Ubuntu), fontconfig-devel (Fedora)",
+    },
+];
+
+impl NativeLib {
+    fn owns_symbol(&self, symbol: &str) -> bool {
+        // MSVC помечает импортируемые из DLL символы префиксом `__imp_`
+        let symbol = symbol.trim_start_matches("__imp_");
+        self.symbols
+            .iter()
+            .any(|s| if s.ends_with('_') { symbol.starts_with(s) } else { symbol == *s })
+    }
+}
+
+/// Имя библиотеки (для `-l`) по недостающему заголовку C, если библиотека известна.
+pub fn library_for_header(header: &str) -> Option<&'static str> {
+    NATIVE_LIBS
+        .iter()
+        .find(|l| l.headers.iter().any(|h| header.starts_with(h)))
+        .map(|l| l.names[0])
+}
+
+/// Разбирает вывод линкера (GNU ld, lld, ld64, MSVC link.exe) и pkg-config.
+pub fn diagnose(text: &str) -> Vec<LinkerProblem> {
+    let mut missing: Vec<String> = Vec::new();
+    let mut pkg_config: Vec<String> = Vec::new();
+    let mut undefined: Vec<String> = Vec::new();
+    let mut duplicate: Vec<String> = Vec::new();
+
+    for line in text.lines().map(str::trim) {
+        let lib = after(line, "cannot find -l")
+            .or_else(|| after(line, "library not found for -l"))
+            .or_else(|| after(line, "unable to find library -l"))
+            .map(|rest| until(rest, &[' ', ':', ',', '\'']))
+            .or_else(|| after(line, "could not find native static library `").map(|r| until(r, &['`'])))
+            .or_else(|| {
+                after(line, "cannot open input file '").map(|r| until(r, &['\'']).trim_end_matches(".lib"))
+            });
+        if let Some(lib) = lib {
+            push_unique(&mut missing, lib);
+        }
+
+        let pkg = after(line, "The system library `")
+            .map(|r| until(r, &['`']))
+            .or_else(|| {
+                line.contains("was not found in the pkg-config search path")
+                    .then(|| after(line, "Package "))
+                    .flatten()
+                    .map(|r| until(r, &[' ', ',']))
+            });
+        if let Some(pkg) = pkg {
+            push_unique(&mut pkg_config, pkg);
+        }
+
+        let symbol = after(line, "undefined reference to `")
+            .map(|r| until(r, &['\'', '`']))
+            .or_else(|| after(line, "undefined symbol: ").map(|r| until(r, &[' '])))
+            .or_else(|| after(line, "unresolved external symbol ").map(|r| until(r, &[' '])))
+            // ld64: `"_sym", referenced from:`
+            .or_else(|| {
+                line.ends_with("referenced from:")
+                    .then(|| after(line, "\""))
+                    .flatten()
+                    .map(|r| until(r, &['"']).trim_start_matches('_'))
+            });
+        if let Some(symbol) = symbol {
+            push_unique(&mut undefined, symbol);
+        }
+
+        let dup = after(line, "multiple definition of `")
+            .map(|r| until(r, &['\'', '`']))
+            .or_else(|| after(line, "duplicate symbol: ").map(|r| until(r, &[' '])))
+            .or_else(|| after(line, "duplicate symbol '").map(|r| until(r, &['\'']).trim_start_matches('_')))
+            .or_else(|| {
+                line.contains("already defined in")
+                    .then(|| line.split_whitespace().find(|w| w.starts_with('"') || w.starts_with('_')))
+                    .flatten()
+                    .map(|w| w.trim_matches('"'))
+            });
+        if let Some(dup) = dup {
+            push_unique(&mut duplicate, dup);
+        }
+    }
+
+    let mut problems: Vec<LinkerProblem> = missing.into_iter().map(LinkerProblem::MissingLibrary).collect();
+    problems.extend(pkg_config.into_iter().map(LinkerProblem::MissingPkgConfig));
+    if !undefined.is_empty() {
+        problems.push(LinkerProblem::UndefinedSymbols(undefined));
+    }
+    if !duplicate.is_empty() {
+        problems.push(LinkerProblem::DuplicateSymbols(duplicate));
+    }
+    problems
+}
+
+/// Исправления для одной проблемы: сначала автоматические, затем советы.
+fn plan(problem: &LinkerProblem, locked: &BTreeMap<String, String>) -> Vec<LinkerFix> {
+    let mut fixes = Vec::new();
+    match problem {
+        LinkerProblem::MissingLibrary(name) | LinkerProblem::MissingPkgConfig(name) => {
+            let known = NATIVE_LIBS.iter().find(|l| l.names.contains(&name.as_str()));
+            if let Some((krate, feature)) = known.and_then(|l| l.sys_crate) {
+                // фича помогает, только если `*-sys` крейт уже есть в дереве зависимостей
+                if let Some(version) = locked.get(krate) {
+                    fixes.push(LinkerFix::EnableFeature {
+                        krate: krate.to_string(),
+                        version: version.clone(),
+                        feature: feature.to_string(),
+                    });
+                }
+            }
+            fixes.push(LinkerFix::Advice(match known {
+                Some(lib) => format!("Install the system package that provides `{name}`: {}.", lib.packages),
+                None => format!(
+                    "Install the development package that provides `{name}` (usually lib{name}-dev or {name}-devel)."
+                ),
+            }));
+            fixes.push(LinkerFix::Advice(
+                "If the library is installed in a non-standard location, set PKG_CONFIG_PATH or print \
+                 `cargo:rustc-link-search=native=<dir>` from build.rs."
+                    .to_string(),
+            ));
+        }
+        LinkerProblem::UndefinedSymbols(symbols) => {
+            let owner = NATIVE_LIBS.iter().find(|l| symbols.iter().any(|s| l.owns_symbol(s)));
+            match owner {
+                Some(lib) => {
+                    fixes.push(LinkerFix::LinkLib(lib.names[0].to_string()));
+                    fixes.push(LinkerFix::Advice(format!(
+                        "Symbols {} come from `{}`; make sure it is installed: {}.",
+                        list(symbols),
+                        lib.names[0],
+                        lib.packages
+                    )));
+                }
+                None => fixes.push(LinkerFix::Advice(format!(
+                    "No linked library defines {}. Add `#[link(name = \"...\")]` to the `extern` block \
+                     or print `cargo:rustc-link-lib=<lib>` from build.rs.",
+                    list(symbols)
+                ))),
+            }
+        }
+        LinkerProblem::DuplicateSymbols(symbols) => {
+            fixes.push(LinkerFix::Advice(format!(
+                "Symbols {} are defined more than once, usually because two crates link the same \
+                 native library. Check `cargo tree -d` for duplicate `*-sys` crates.",
+                list(symbols)
+            )));
+            fixes.push(LinkerFix::Advice(
+                "A package that links a native library should declare `links = \"<lib>\"` in its \
+                 [package] section so Cargo refuses to link two copies."
+                    .to_string(),
+            ));
+        }
+    }
+    fixes
+}
+
+pub struct LinkerExpert<'a> {
+    changes: &'a ChangeSet,
+}
+
+impl<'a> LinkerExpert<'a> {
+    pub fn new(changes: &'a ChangeSet) -> Self {
+        Self { changes }
+    }
+
+    /// Разбирает ошибку линковки пакета с манифестом `manifest_rel_path`, пробует
+    /// автоматические исправления (каждое проверяется полной сборкой) и печатает советы.
+    /// Возвращает Ok(true), если правка применена.
+    pub async fn fix_linker_issue_at(
+        &self,
+        issue: &CompilerMessage,
+        manifest_rel_path: &str,
+        baseline: &[CompilerMessage],
+    ) -> Result<bool> {
+        println!("    -> Detected a linker issue. Engaging Linker Expert.");
+        let text = format!("{}\n{}", issue.full_text(), issue.rendered.as_deref().unwrap_or(""));
+        let problems = diagnose(&text);
+        if problems.is_empty() {
+            println!("    -> Could not recognise the linker output.");
+            return Ok(false);
+        }
+
+        self.try_fixes(&problems, issue, manifest_rel_path, baseline, Vec::new()).await
+    }
+
+    /// Пробует автоматические исправления распознанных проблем по очереди; если ни одно
+    /// не прошло, печатает советы (`advice` — уже собранные вызывающим).
+    pub async fn try_fixes(
+        &self,
+        problems: &[LinkerProblem],
+        issue: &CompilerMessage,
+        manifest_rel_path: &str,
+        baseline: &[CompilerMessage],
+        mut advice: Vec<String>,
+    ) -> Result<bool> {
+        // Cargo.lock лежит в корне воркспейса, а не обязательно в текущем каталоге
+        let lock_path = Workspace::load(Path::new("."))
+            .map(|ws| ws.workspace_root.join("Cargo.lock"))
+            .unwrap_or_else(|_| PathBuf::from("Cargo.lock"));
+        let locked = locked_packages(&lock_path);
+        for problem in problems {
+            println!("    -> Linker: {}", problem.describe());
+            for fix in plan(problem, &locked) {
+                if let LinkerFix::Advice(text) = fix {
+                    advice.push(text);
+                    continue;
+                }
+                let Some(set) = self.build_patch(&fix, manifest_rel_path).await? else {
+                    continue;
+                };
+                println!("    -> Trying: {}", fix.describe());
+                if self.verify_and_apply(set, issue, baseline).await? {
+                    return Ok(true);
+                }
+            }
+        }
+
+        println!("    -> No automatic fix worked. Suggestions:");
+        for text in advice {
+            println!("       * {text}");
+        }
+        Ok(false)
+    }
+
+    /// Проверяет правку полной сборкой, показывает на ревью и применяет.
+    pub async fn verify_and_apply(&self, set: PatchSet, issue: &CompilerMessage, baseline: &[CompilerMessage]) -> Result<bool> {
+        if !self.verify_fix(&set, issue, baseline).await? {
+            return Ok(false);
+        }
+        let set = match self.changes.review(set).await? {
+            Review::Accept(set) => set,
+            // правку пользователя проверяем той же сборкой, что и исходное предложение
+            Review::Edited(set) => {
+                if !self.verify_fix(&set, issue, baseline).await? {
+                    println!("    -> Edited fix failed verification. Skipping.");
+                    return Ok(false);
+                }
+                set
+            }
+            Review::Reject(_) => {
+                println!("    -> Fix rejected by user.");
+                return Ok(false);
+            }
+        };
+        println!("    -> Verification successful! Applying the fix.");
+        self.changes.apply(&set).await?;
+        Ok(true)
+    }
+
+    async fn build_patch(&self, fix: &LinkerFix, manifest_rel_path: &str) -> Result<Option<PatchSet>> {
+        let manifest = tokio::fs::read_to_string(manifest_rel_path)
+            .await
+            .with_context(|| format!("Failed to read {manifest_rel_path}"))?;
+        match fix {
+            LinkerFix::EnableFeature { krate, version, feature } => {
+                let mut doc = manifest.parse::<DocumentMut>().context("Failed to parse Cargo.toml")?;
+                if doc.get("dependencies").is_none() {
+                    doc["dependencies"] = toml_edit::table();
+                }
+                let Some(deps) = doc["dependencies"].as_table_mut() else {
+                    return Ok(None);
+                };
+                let features = [feature.clone()];
+                match deps.get_mut(krate) {
+                    Some(existing) => merge_features(existing, &features),
+                    // прямая зависимость с фичей: Cargo объединит фичи с транзитивной
+                    None => {
+                        let mut table = InlineTable::new();
+                        table.insert("version", Value::from(version.as_str()));
+                        table.insert("features", Value::from(Array::from_iter(features.iter().map(String::as_str))));
+                        deps.insert(krate, Item::Value(table.into()));
+                    }
+                }
+                let after = doc.to_string();
+                Ok((after != manifest).then(|| PatchSet::single(manifest_rel_path, Some(manifest), after)))
+            }
+            LinkerFix::LinkLib(lib) => {
+                let Some(build_rs) = build_script::script_path(manifest_rel_path, &manifest) else {
+                    return Ok(None);
+                };
+                let directive = format!("    println!(\"cargo:rustc-link-lib={lib}\");");
+                match tokio::fs::read_to_string(&build_rs).await {
+                    Ok(source) => {
+                        if source.contains(&format!("cargo:rustc-link-lib={lib}")) {
+                            return Ok(None);
+                        }
+                        let Some(after) = insert_into_main(&source, &directive) else {
+                            return Ok(None);
+                        };
+                        Ok(Some(PatchSet::single(&build_rs, Some(source), after)))
+                    }
+                    Err(_) => {
+                        let content = format!("fn main() {{\n{directive}\n}}\n");
+                        Ok(Some(PatchSet::single(&build_rs, None, content)))
+                    }
+                }
+            }
+            LinkerFix::Advice(_) => Ok(None),
+        }
+    }
+
+    /// Линковка проверяется только полной сборкой (`cargo check` до линкера не доходит).
+    async fn verify_fix(&self, set: &PatchSet, issue: &CompilerMessage, baseline: &[CompilerMessage]) -> Result<bool> {
+        let temp = ScratchCopy::create(Path::new(".")).await?;
+        temp.write_set(set).await?;
+        // сломанный манифест не даёт диагностик, а cargo просто завершается с ошибкой
+        if let Err(e) = Workspace::load(temp.path()) {
+            println!("    -> Verification failed: {}", format!("{e:#}").lines().next().unwrap_or_default());
+            return Ok(false);
+        }
+
+        let messages = run_cargo_in(temp.path(), &["build"])?;
+        match ErrorCheck::new(std::slice::from_ref(issue), baseline).evaluate(&messages) {
+            Ok(_) => Ok(true),
+            Err(reason) => {
+                println!("    -> Verification failed: {}", reason.lines().next().unwrap_or_default());
+                Ok(false)
+            }
+        }
+    }
+}
+
+/// Версии пакетов из Cargo.lock: по ним видно, какие `*-sys` крейты уже в дереве.
+fn locked_packages(lock_path: &Path) -> BTreeMap<String, String> {
+    let mut packages = BTreeMap::new();
+    let Some(doc) = std::fs::read_to_string(lock_path).ok().and_then(|s| s.parse::<DocumentMut>().ok()) else {
+        return packages;
+    };
+    if let Some(list) = doc.get("package").and_then(Item::as_array_of_tables) {
+        for pkg in list.iter() {
+            if let (Some(name), Some(version)) = (pkg.get("name").and_then(Item::as_str), pkg.get("version").and_then(Item::as_str)) {
+                packages.insert(name.to_string(), version.to_string());
+            }
+        }
+    }
+    packages
+}
+
+/// Вставляет строку первой инструкцией `fn main` build-скрипта. `main` ищется через `syn`
+/// среди элементов верхнего уровня: комментарии и строки с `fn main` в других местах не мешают.
+pub fn insert_into_main(source: &str, line: &str) -> Option<String> {
+    let file = syn::parse_file(source).ok()?;
+    let main = file.items.iter().find_map(|item| match item {
+        syn::Item::Fn(f) if f.sig.ident == "main" => Some(f),
+        _ => None,
+    })?;
+    // позиция `{` тела: строки с 1, колонки в символах
+    let open = main.block.brace_token.span.open().start();
+    let line_start: usize = source.split_inclusive('\n').take(open.line - 1).map(str::len).sum();
+    let brace = line_start + source[line_start..].char_indices().nth(open.column)?.0;
+    Some(format!("{}\n{line}{}", &source[..=brace], &source[brace + 1..]))
+}
+
+fn after<'t>(line: &'t str, pattern: &str) -> Option<&'t str> {
+    line.find(pattern).map(|i| &line[i + pattern.len()..])
+}
+
+fn until<'t>(text: &'t str, stops: &[char]) -> &'t str {
+    text.split(|c| stops.contains(&c)).next().unwrap_or(text)
+}
+
+fn push_unique(items: &mut Vec<String>, item: &str) {
+    if !item.is_empty() && !items.iter().any(|i| i == item) {
+        items.push(item.to_string());
+    }
+}
+
+/// Первые несколько имён через запятую — длинные списки символов в логе не нужны.
+fn list(items: &[String]) -> String {
+    const SHOWN: usize = 5;
+    let mut out = items.iter().take(SHOWN).map(|s| format!("`{s}`")).collect::<Vec<_>>().join(", ");
+    if items.len() > SHOWN {
+        out.push_str(&format!(" and {} more", items.len() - SHOWN));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inserts_into_the_real_main() {
+        let source = "// fn main is below\nfn main_helper() {}\n\nfn main() -> Result<(), ()> {\n    main_helper();\n    Ok(())\n}\n";
+        let line = "    println!(\"cargo:rustc-link-lib=z\");";
+        assert_eq!(
+            insert_into_main(source, line).unwrap(),
+            "// fn main is below\nfn main_helper() {}\n\nfn main() -> Result<(), ()> {\n    println!(\"cargo:rustc-link-lib=z\");\n    main_helper();\n    Ok(())\n}\n"
+        );
+        assert_eq!(insert_into_main("fn main() { run(); }", "    x();").unwrap(), "fn main() {\n    x(); run(); }");
+        assert!(insert_into_main("fn helper() {}\n", line).is_none());
+    }
+
+    #[test]
+    fn matches_library_symbols_exactly() {
+        let owner = |symbol: &str| NATIVE_LIBS.iter().find(|l| l.owns_symbol(symbol)).map(|l| l.names[0]);
+        assert_eq!(owner("inflateInit2_"), Some("z"));
+        assert_eq!(owner("gzopen"), Some("z"));
+        assert_eq!(owner("__imp_SSL_new"), Some("ssl"));
+        assert_eq!(owner("sqlite3_open_v2"), Some("sqlite3"));
+        assert_eq!(owner("FcInit"), Some("fontconfig"));
+        // короткие префиксы больше не цепляют чужие символы
+        for foreign in ["gzip_compress", "Fcntl", "inflate_block", "PQueue_push", "lo_hash", "ERR_my_app"] {
+            assert_eq!(owner(foreign), None, "{foreign}");
+        }
+    }
+}
@@ -0,0 +1,282 @@
+use super::llm_replay::{RecordingBackend, ReplayBackend};
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::Deserialize;
+use std::env;
+use std::path::PathBuf;
+use std::time::Duration;
+
+pub const DEFAULT_TIMEOUT_SECS: u64 = 120;
+const TEMPERATURE: f32 = 0.2;
+
+/// Провайдер модели: принимает промпт и возвращает текст ответа.
+#[async_trait]
+pub trait LlmBackend: Send + Sync {
+    /// Короткое описание для логов: провайдер, адрес и модель.
+    fn describe(&self) -> String;
+
+    /// Имя модели — для метаданных решений в кэше.
+    fn model(&self) -> String;
+
+    /// `json == true` — попросить сервер вернуть валидный JSON-объект.
+    async fn complete(&self, prompt: &str, json: bool) -> Result<String>;
+}
+
+/// Выбор провайдера. Пустые поля берутся из переменных окружения.
+#[derive(Debug, Clone, Default)]
+pub struct LlmConfig {
+    pub backend: Option<String>,
+    pub url: Option<String>,
+    pub model: Option<String>,
+    pub api_key: Option<String>,
+    /// Таймаут одного запроса к модели, в секундах.
+    pub timeout_secs: u64,
+    /// Записывать все промпты и ответы в JSONL-фикстуру.
+    pub record: Option<PathBuf>,
+    /// Отвечать из фикстуры вместо обращения к серверу модели.
+    pub replay: Option<PathBuf>,
+}
+
+impl LlmConfig {
+    /// Фикстуры (запись или воспроизведение) требуют детерминированных промптов.
+    pub fn uses_fixture(&self) -> bool {
+        self.record.is_some() || self.replay.is_some()
+    }
+}
+
+/// Создаёт провайдер по конфигурации (с записью или воспроизведением фикстуры).
+/// Переменные окружения `RUSTY_FIXER_LLM_*` разбирает clap; для Ollama, если URL
+/// или модель не заданы, по-прежнему работают `OLLAMA_BASE_URL` и `OLLAMA_MODEL`,
+/// а `OPENAI_API_KEY` подставляется только для самого api.openai.com.
+pub fn from_config(config: &LlmConfig) -> Result<Box<dyn LlmBackend>> {
+    if let Some(path) = &config.replay {
+        return Ok(Box::new(ReplayBackend::load(path)?));
+    }
+    let backend = live_backend(config)?;
+    match &config.record {
+        Some(path) => Ok(Box::new(RecordingBackend::new(backend, path)?)),
+        None => Ok(backend),
+    }
+}
+
+fn live_backend(config: &LlmConfig) -> Result<Box<dyn LlmBackend>> {
+    let kind = config.backend.as_deref().unwrap_or("ollama");
+    let url = config.url.clone();
+    let model = config.model.clone();
+    let http = Client::builder().timeout(Duration::from_secs(config.timeout_secs)).build()?;
+
+    match kind.to_lowercase().as_str() {
+        "ollama" => Ok(Box::new(OllamaBackend {
+            http,
+            base_url: url
+                .or_else(|| env::var("OLLAMA_BASE_URL").ok())
+                .unwrap_or_else(|| "http://127.0.0.1:11434".to_string()),
+            model: model
+                .or_else(|| env::var("OLLAMA_MODEL").ok())
+                .unwrap_or_else(|| "llama3:8b".to_string()),
+        })),
+        "openai" | "openai-compatible" | "vllm" | "lmstudio" => {
+            let base_url = url.unwrap_or_else(|| "http://127.0.0.1:8000".to_string());
+            // ключ OpenAI из окружения не должен уходить на произвольный `--llm-url`
+            let api_key = config
+                .api_key
+                .clone()
+                .or_else(|| is_openai_host(&base_url).then(|| env::var("OPENAI_API_KEY").ok()).flatten());
+            Ok(Box::new(OpenAiBackend {
+                http,
+                base_url,
+                model: model.unwrap_or_else(|| "default".to_string()),
+                api_key,
+            }))
+        }
+        "llamacpp" | "llama.cpp" | "llama-cpp" => Ok(Box::new(LlamaCppBackend {
+            http,
+            base_url: url.unwrap_or_else(|| "http://127.0.0.1:8080".to_string()),
+        })),
+        other => bail!("Unknown LLM backend `{other}`; expected ollama, openai or llamacpp"),
+    }
+}
+
+fn is_openai_host(url: &str) -> bool {
+    reqwest::Url::parse(url).is_ok_and(|u| u.host_str() == Some("api.openai.com"))
+}
+
+fn trim_url(url: &str) -> &str {
+    url.trim_end_matches('/')
+}
+
+/// Ollama: `/api/chat`.
+pub struct OllamaBackend {
+    http: Client,
+    base_url: String,
+    model: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct OllamaChatResponse {
+    message: ChatMessage,
+    // другие поля, которые Ollama может прислать, нам не мешают (serde их игнорирует)
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatMessage {
+    content: String,
+}
+
+#[async_trait]
+impl LlmBackend for OllamaBackend {
+    fn describe(&self) -> String {
+        format!("ollama at {} (model {})", self.base_url, self.model)
+    }
+
+    fn model(&self) -> String {
+        self.model.clone()
+    }
+
+    async fn complete(&self, prompt: &str, json: bool) -> Result<String> {
+        // ВАЖНО: отключаем streaming, иначе Ollama вернёт несколько JSON-объектов подряд
+        // и парсер получит "trailing characters".
+        let url = format!("{}/api/chat", trim_url(&self.base_url));
+        let body = serde_json::json!({
+          "model": self.model,
+          "messages": [{"role":"user", "content": prompt}],
+          "options": { "temperature": TEMPERATURE },
+          "format": if json { serde_json::json!("json") } else { serde_json::Value::Null },
+          "stream": false
+        });
+
+        let res = self.http.post(&url).json(&body).send().await?;
+        if !res.status().is_success() {
+            return Err(anyhow!("Ollama API request failed with status {}", res.status()));
+        }
+        let parsed = res.json::<OllamaChatResponse>().await
+            .context("Failed to parse Ollama response")?;
+        Ok(parsed.message.content)
+    }
+}
+
+/// Любой OpenAI-совместимый сервер: vLLM, LM Studio, llama.cpp server и т.п.
+pub struct OpenAiBackend {
+    http: Client,
+    base_url: String,
+    model: String,
+    api_key: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAiResponse {
+    choices: Vec<OpenAiChoice>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAiChoice {
+    message: ChatMessage,
+}
+
+#[async_trait]
+impl LlmBackend for OpenAiBackend {
+    fn describe(&self) -> String {
+        format!("openai-compatible at {} (model {})", self.base_url, self.model)
+    }
+
+    fn model(&self) -> String {
+        self.model.clone()
+    }
+
+    async fn complete(&self, prompt: &str, json: bool) -> Result<String> {
+        let url = format!("{}/v1/chat/completions", trim_url(&self.base_url));
+        let mut body = serde_json::json!({
+          "model": self.model,
+          "messages": [{"role":"user", "content": prompt}],
+          "temperature": TEMPERATURE,
+          "stream": false
+        });
+        if json {
+            body["response_format"] = serde_json::json!({ "type": "json_object" });
+        }
+
+        let mut req = self.http.post(&url).json(&body);
+        if let Some(key) = &self.api_key {
+            req = req.bearer_auth(key);
+        }
+        let res = req.send().await?;
+        if !res.status().is_success() {
+            return Err(anyhow!("OpenAI-compatible API request failed with status {}", res.status()));
+        }
+        let parsed = res.json::<OpenAiResponse>().await
+            .context("Failed to parse OpenAI-compatible response")?;
+        parsed
+            .choices
+            .into_iter()
+            .next()
+            .map(|c| c.message.content)
+            .ok_or_else(|| anyhow!("OpenAI-compatible response contained no choices"))
+    }
+}
+
+/// Нативный эндпоинт llama.cpp server: `/completion`.
+pub struct LlamaCppBackend {
+    http: Client,
+    base_url: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct LlamaCppResponse {
+    content: String,
+}
+
+#[async_trait]
+impl LlmBackend for LlamaCppBackend {
+    fn describe(&self) -> String {
+        format!("llama.cpp at {}", self.base_url)
+    }
+
+    // сервер обслуживает одну загруженную модель и имени не сообщает
+    fn model(&self) -> String {
+        "llama.cpp".to_string()
+    }
+
+    async fn complete(&self, prompt: &str, json: bool) -> Result<String> {
+        let url = format!("{}/completion", trim_url(&self.base_url));
+        let mut body = serde_json::json!({
+          "prompt": prompt,
+          "temperature": TEMPERATURE,
+          "n_predict": -1,
+          "stream": false
+        });
+        if json {
+            // грамматика llama.cpp ограничивает вывод JSON-объектом
+            body["json_schema"] = serde_json::json!({ "type": "object" });
+        }
+
+        let res = self.http.post(&url).json(&body).send().await?;
+        if !res.status().is_success() {
+            return Err(anyhow!("llama.cpp request failed with status {}", res.status()));
+        }
+        let parsed = res.json::<LlamaCppResponse>().await
+            .context("Failed to parse llama.cpp response")?;
+        Ok(parsed.content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn openai_key_goes_only_to_openai() {
+        for url in ["https://api.openai.com", "https://api.openai.com/v1/"] {
+            assert!(is_openai_host(url), "{url}");
+        }
+        for url in [
+            "http://127.0.0.1:8000",
+            "https://api.openai.com.example.org",
+            "https://example.org/api.openai.com",
+            "https://api.openai.com@example.org",
+            "api.openai.com",
+        ] {
+            assert!(!is_openai_host(url), "{url}");
+        }
+    }
+}
@@ -1,8 +1,7 @@
-use anyhow::{anyhow, Context, Result};
-use reqwest::Client;
+use super::llm_backend::{self, LlmBackend, LlmConfig};
+use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
 use serde::de::DeserializeOwned;
-use std::env;
 use std::fmt::Debug;
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -20,55 +19,28 @@ pub struct CargoSuggestionDetails {
 }
 
 pub struct LLMInterface {
-    http_async: Client,
-    base_url: String,
-    model: String,
-    timeout_secs: u64,
+    backend: Box<dyn LlmBackend>,
 }
 
-#[derive(Debug, Deserialize)]
-struct OllamaChatResponse {
-    message: OllamaMessage,
-    // другие поля, которые Ollama может прислать, нам не мешают (serde их игнорирует)
-}
+impl LLMInterface {
+    pub fn new(config: &LlmConfig) -> Result<Self> {
+        Ok(Self::with_backend(llm_backend::from_config(config)?))
+    }
 
-#[derive(Debug, Deserialize)]
-struct OllamaMessage {
-    content: String,
-}
+    pub fn with_backend(backend: Box<dyn LlmBackend>) -> Self {
+        Self { backend }
+    }
 
-impl LLMInterface {
-    pub fn new() -> Result<Self> {
-        let base_url = env::var("OLLAMA_BASE_URL").unwrap_or_else(|_| "http://127.0.0.1:11434".to_string());
-        let model = env::var("OLLAMA_MODEL").unwrap_or_else(|_| "llama3:8b".to_string());
-
-        Ok(Self {
-            http_async: Client::builder().build()?,
-            base_url,
-            model,
-            timeout_secs: 120,
-        })
+    pub fn describe(&self) -> String {
+        self.backend.describe()
+    }
+
+    pub fn model(&self) -> String {
+        self.backend.model()
     }
 
     async fn chat(&self, prompt: &str, format: &str) -> Result<String> {
-        // ВАЖНО: отключаем streaming, иначе Ollama вернёт несколько JSON-объектов подряд
-        // и парсер получит "trailing characters".
-        let url = format!("{}/api/chat", self.base_url);
-        let body = serde_json::json!({
-          "model": self.model,
-          "messages": [{"role":"user", "content": prompt}],
-          "options": { "temperature": 0.2 },
-          "format": if format.is_empty() { serde_json::Value::Null } else { serde_json::json!(format) },
-          "stream": false
-        });
-
-        let res = self.http_async.post(&url).json(&body).send().await?;
-        if !res.status().is_success() {
-            return Err(anyhow!("Ollama API request failed with status {}", res.status()));
-        }
-        let parsed = res.json::<OllamaChatResponse>().await
-            .context("Failed to parse Ollama response")?;
-        Ok(parsed.message.content)
+        self.backend.complete(prompt, format == "json").await
     }
 
     async fn request_json<T: DeserializeOwned + Debug>(&self, initial_prompt: &str) -> Result<T> {
@@ -98,7 +70,10 @@ Compiler Error:
         self.request_json(&prompt).await
     }
 
-    pub async fn generate_full_fix(&self, error_message: &str, full_code: &str, web_context: &str) -> Result<String> {
+    pub async fn generate_full_fix(&self, error_message: &str, full_code: &str, project_context: &str, examples: &str, web_context: &str, rejected: &[String]) -> Result<String> {
+        let rejected_context = rejected_section(rejected);
+        let project_context = project_section(project_context);
+        let examples = examples_section(examples);
         let prompt = format!(r#"
 Fix the Rust code.
 RULES:
@@ -109,9 +84,9 @@ RULES:
 {error_message}
 --- FULL SOURCE CODE ---
 {full_code}
---- CONTEXT FROM ONLINE SEARCH ---
+{project_context}{examples}--- CONTEXT FROM ONLINE SEARCH ---
 {web_context}
----
+{rejected_context}---
 Your Corrected Full Source Code:
 "#);
         let raw = self.chat(&prompt, "").await?;
@@ -123,16 +98,93 @@ Your Corrected Full Source Code:
             .to_string())
     }
 
-    pub async fn generate_cargo_fix(&self, error_message: &str) -> Result<CargoSuggestionDetails> {
+    /// Просит модель вернуть только точечные правки в формате SEARCH/REPLACE
+    /// по фрагменту файла вокруг ошибки (без перегенерации всего файла).
+    #[allow(clippy::too_many_arguments)]
+    pub async fn generate_span_fix(&self, error_message: &str, file_path: &str, excerpt: &str, project_context: &str, examples: &str, web_context: &str, rejected: &[String]) -> Result<String> {
+        let rejected_context = rejected_section(rejected);
+        let project_context = project_section(project_context);
+        let examples = examples_section(examples);
+        let prompt = format!(r#"
+Fix the Rust code with minimal, targeted edits.
+RULES:
+1) Your output MUST BE ONLY one or more edit blocks in exactly this format:
+<<<<<<< SEARCH
+exact lines copied from the current file
+=======
+replacement lines
+>>>>>>> REPLACE
+2) SEARCH lines must be copied verbatim from the file WITHOUT the line-number prefix,
+   and must be unique in the file: include a couple of surrounding lines if needed.
+3) If the fix needs changes in other files, put a line `FILE: relative/path.rs` (relative to
+   the project root) before their blocks. To create a new file, leave SEARCH empty.
+4) Change only what is needed to fix the error. No explanations or markdown.
+
+--- COMPILER ERROR ---
+{error_message}
+--- CODE AROUND THE ERROR ({file_path}) ---
+{excerpt}
+{project_context}{examples}--- CONTEXT FROM ONLINE SEARCH ---
+{web_context}
+{rejected_context}---
+Your Edit Blocks:
+"#);
+        let raw = self.chat(&prompt, "").await?;
+        Ok(raw.trim().to_string())
+    }
+
+    /// `missing_feature == true` — крейт уже подключён, не хватает cargo-фичи.
+    /// `package_context` — описание пакета из `cargo metadata` (текущие зависимости и фичи).
+    pub async fn generate_cargo_fix(&self, error_message: &str, missing_feature: bool, package_context: &str) -> Result<CargoSuggestionDetails> {
+        let task = if missing_feature {
+            "Analyze a Rust error caused by a dependency feature that is not enabled.\n\
+             TASK: Extract the crate name, a suitable version, and the features that must be enabled."
+        } else {
+            "Analyze a Rust error about a missing dependency.\n\
+             TASK: Extract the crate name, a suitable version, and any required features."
+        };
+        let package_context = if package_context.is_empty() {
+            String::new()
+        } else {
+            format!("Current package:\n{package_context}")
+        };
         let prompt = format!(r#"
-Analyze a Rust error about a missing dependency.
-TASK: Extract the crate name, a suitable version, and any required features.
+{task}
 CRITICAL RULES:
 1) Return a valid JSON object with keys crate_name, version, features (array of strings).
 
 Compiler error:
 {error_message}
-"#);
+{package_context}"#);
         self.request_json::<CargoSuggestionDetails>(&prompt).await
     }
 }
+
+fn rejected_section(rejected: &[String]) -> String {
+    if rejected.is_empty() {
+        return String::new();
+    }
+    format!(
+        "--- REJECTED FIXES (a reviewer refused these; do NOT propose them again) ---\n{}\n",
+        rejected.join("\n")
+    )
+}
+
+/// Пакет и определения из других мест проекта, на которые ссылается ошибка; пусто — секции нет.
+fn project_section(items: &str) -> String {
+    if items.trim().is_empty() {
+        return String::new();
+    }
+    format!("--- PROJECT CONTEXT (package and related definitions, for reference) ---\n{}\n", items.trim_end())
+}
+
+/// Похожие ошибки, исправленные раньше (из кэша решений); пусто — секции нет.
+fn examples_section(examples: &str) -> String {
+    if examples.trim().is_empty() {
+        return String::new();
+    }
+    format!(
+        "--- SIMILAR PAST FIXES (from other code; adapt the idea, do not copy SEARCH lines) ---\n{}\n",
+        examples.trim_end()
+    )
+}
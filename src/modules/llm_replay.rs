@@ -0,0 +1,121 @@
+use super::llm_backend::LlmBackend;
+use anyhow::{anyhow, Context, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Одна запись фикстуры: промпт, ответ модели и ключ для поиска при воспроизведении.
+#[derive(Debug, Serialize, Deserialize)]
+struct FixtureEntry {
+    hash: String,
+    json: bool,
+    prompt: String,
+    response: String,
+}
+
+/// Стабильный между запусками и версиями Rust хэш промпта (FNV-1a, 64 бита).
+pub fn prompt_hash(prompt: &str, json: bool) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    let bytes = prompt.as_bytes().iter().chain(if json { b"#json" } else { b"#text" });
+    for b in bytes {
+        hash ^= u64::from(*b);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{hash:016x}")
+}
+
+/// Оборачивает настоящий провайдер и дописывает каждую пару промпт/ответ в JSONL-фикстуру.
+pub struct RecordingBackend {
+    inner: Box<dyn LlmBackend>,
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl RecordingBackend {
+    pub fn new(inner: Box<dyn LlmBackend>, path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open LLM fixture {} for recording", path.display()))?;
+        Ok(Self { inner, path: path.to_path_buf(), file: Mutex::new(file) })
+    }
+}
+
+#[async_trait]
+impl LlmBackend for RecordingBackend {
+    fn describe(&self) -> String {
+        format!("{} (recording to {})", self.inner.describe(), self.path.display())
+    }
+
+    fn model(&self) -> String {
+        self.inner.model()
+    }
+
+    async fn complete(&self, prompt: &str, json: bool) -> Result<String> {
+        let response = self.inner.complete(prompt, json).await?;
+        let entry = FixtureEntry {
+            hash: prompt_hash(prompt, json),
+            json,
+            prompt: prompt.to_string(),
+            response: response.clone(),
+        };
+        let line = serde_json::to_string(&entry)?;
+        let mut file = self.file.lock().unwrap();
+        writeln!(file, "{line}")
+            .and_then(|_| file.flush())
+            .with_context(|| format!("Failed to append to LLM fixture {}", self.path.display()))?;
+        Ok(response)
+    }
+}
+
+/// Отдаёт записанные ответы по хэшу промпта, не обращаясь к серверу модели.
+/// Повторяющиеся промпты получают ответы в порядке записи; последний ответ повторяется.
+pub struct ReplayBackend {
+    path: PathBuf,
+    responses: Mutex<HashMap<String, VecDeque<String>>>,
+}
+
+impl ReplayBackend {
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open LLM fixture {}", path.display()))?;
+        let mut responses: HashMap<String, VecDeque<String>> = HashMap::new();
+        for (n, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry: FixtureEntry = serde_json::from_str(&line)
+                .with_context(|| format!("Malformed fixture entry at {}:{}", path.display(), n + 1))?;
+            responses.entry(entry.hash).or_default().push_back(entry.response);
+        }
+        Ok(Self { path: path.to_path_buf(), responses: Mutex::new(responses) })
+    }
+}
+
+#[async_trait]
+impl LlmBackend for ReplayBackend {
+    fn describe(&self) -> String {
+        format!("replay from {}", self.path.display())
+    }
+
+    fn model(&self) -> String {
+        "replay".to_string()
+    }
+
+    async fn complete(&self, prompt: &str, json: bool) -> Result<String> {
+        let hash = prompt_hash(prompt, json);
+        let mut responses = self.responses.lock().unwrap();
+        let queue = responses.get_mut(&hash).ok_or_else(|| {
+            let head: String = prompt.trim().chars().take(120).collect();
+            anyhow!("No recorded LLM response for prompt {hash} in {}: {head}...", self.path.display())
+        })?;
+        let response = if queue.len() > 1 { queue.pop_front() } else { queue.front().cloned() };
+        Ok(response.unwrap_or_default())
+    }
+}
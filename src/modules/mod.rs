@@ -1,9 +1,22 @@
 pub mod llm_interface;
+pub mod llm_backend;
+pub mod llm_replay;
 pub mod web_agent;
 pub mod patch_engine;
 pub mod knowledge_cache;
+pub mod cache_commands;
 pub mod cli;
 pub mod issue_detector;
 pub mod project_analyzer;
+pub mod symbol_index;
+pub mod workspace;
 pub mod cargo_expert;
 pub mod quick_fixes;
+pub mod watcher;
+pub mod change_set;
+pub mod approval;
+pub mod span_patch;
+pub mod rustc_suggestions;
+pub mod linker;
+pub mod build_script;
+pub mod test_runner;
@@ -1,15 +1,17 @@
-use crate::{CargoMessage, CompilerMessage};
+use crate::{run_cargo_in, CompilerMessage};
 use super::llm_interface::LLMInterface;
-use super::knowledge_cache::KnowledgeCache;
+use super::knowledge_cache::{self, CachedFix, KnowledgeCache, FEW_SHOT_EXAMPLES};
+use super::change_set::{ChangeSet, PatchSet};
+use super::approval::Review;
+use super::span_patch::{self, HunkRejected};
+use super::workspace::{ScratchCopy, Workspace};
+use super::project_analyzer;
+use super::test_runner::{self, TestFailure, TestRun};
+use super::issue_detector::issue_key;
 use anyhow::{Result, Context, bail};
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
 use tokio::fs;
-use std::process::{Command, Stdio};
-use std::io::{BufReader, BufRead};
-use std::sync::{Arc, Mutex};
-use std::thread;
-use tempfile::TempDir;
-use walkdir::WalkDir;
 
 pub enum VerificationResult {
     Success,
@@ -19,24 +21,97 @@ pub enum VerificationResult {
 pub struct PatchEngine<'a> {
     llm: &'a LLMInterface,
     cache: &'a KnowledgeCache,
+    changes: &'a ChangeSet,
     error_signature: String,
     error_message: &'a str,
     file_path: &'a str,
     web_context: &'a str,
     no_cache: bool,
+    warning_check: Option<WarningCheck>,
+    error_check: Option<ErrorCheck>,
+    test_check: Option<TestCheck>,
+    focus_line: Option<usize>,
+    /// Строки всех целевых ошибок пакета: вокруг каждой строится фрагмент для модели.
+    batch_lines: Vec<usize>,
+    full_file: bool,
+    strategy_hint: &'a str,
+    /// Команда cargo для первого шага проверки (пусто — `cargo check`), например clippy с линтами.
+    check_command: &'a [String],
+    /// Определения из других файлов проекта, на которые ссылается ошибка.
+    project_context: &'a str,
 }
 
+/// Сколько строк вокруг ошибки показываем модели в режиме точечных правок.
+const EXCERPT_RADIUS: usize = 40;
+
+/// Сколько строк вокруг ошибки сравниваем с кодом прошлых решений при нечётком поиске.
+const SIMILARITY_RADIUS: usize = 5;
+
 impl<'a> PatchEngine<'a> {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         llm: &'a LLMInterface,
         cache: &'a KnowledgeCache,
+        changes: &'a ChangeSet,
         error_signature: String,
         error_message: &'a str,
         file_path: &'a str,
         web_context: &'a str,
         no_cache: bool,
     ) -> Self {
-        Self { llm, cache, error_signature, error_message, file_path, web_context, no_cache }
+        Self { llm, cache, changes, error_signature, error_message, file_path, web_context, no_cache, warning_check: None, error_check: None, test_check: None, focus_line: None, batch_lines: Vec::new(), full_file: false, strategy_hint: "", check_command: &[], project_context: "" }
+    }
+
+    /// Строка, на которую указывает компилятор: вокруг неё строится фрагмент для модели
+    /// и по ней разрешаются неоднозначные совпадения SEARCH.
+    pub fn with_focus_line(mut self, line: usize) -> Self {
+        self.focus_line = Some(line);
+        self
+    }
+
+    /// Старый режим: модель возвращает файл целиком вместо блоков SEARCH/REPLACE.
+    pub fn with_full_file(mut self, full_file: bool) -> Self {
+        self.full_file = full_file;
+        self
+    }
+
+    /// Подсказка по классу ошибки (см. `IssueClassification::strategy_hint`), добавляется к тексту ошибки.
+    pub fn with_strategy_hint(mut self, hint: &'a str) -> Self {
+        self.strategy_hint = hint;
+        self
+    }
+
+    /// Проверять правку другой командой cargo (`clippy -- -W clippy::pedantic`), а не `cargo check`.
+    pub fn with_check_command(mut self, command: &'a [String]) -> Self {
+        self.check_command = command;
+        self
+    }
+
+    /// Связанные определения из проекта (`ProjectAnalyzer::related_items`) для промпта.
+    pub fn with_project_context(mut self, context: &'a str) -> Self {
+        self.project_context = context;
+        self
+    }
+
+    /// Переключает проверку в режим исправления предупреждения.
+    pub fn with_warning_check(mut self, check: WarningCheck) -> Self {
+        self.warning_check = Some(check);
+        self
+    }
+
+    /// Проверка относительно ошибок проекта до правки: прочие ошибки не мешают,
+    /// успех — исчезла хотя бы одна целевая ошибка и не появилось новых.
+    pub fn with_error_check(mut self, check: ErrorCheck) -> Self {
+        self.batch_lines = check.lines.clone();
+        self.error_check = Some(check);
+        self
+    }
+
+    /// Режим исправления упавшего теста: правка должна заставить тест пройти,
+    /// не сломать другие и не трогать сам тест и утверждения.
+    pub fn with_test_check(mut self, check: TestCheck) -> Self {
+        self.test_check = Some(check);
+        self
     }
 
     pub async fn run_and_self_correct(&self) -> Result<()> {
@@ -46,35 +121,55 @@ impl<'a> PatchEngine<'a> {
         let original_code = fs::read_to_string(self.file_path).await
             .with_context(|| format!("Failed to read {}", self.file_path))?;
 
-        // 2) Если есть валидный кэш — используем
+        // Отклонённые пользователем правки — негативный контекст для следующих попыток
+        let mut rejected: Vec<String> = Vec::new();
+
+        // 2) Если есть валидный кэш — используем; решения пробуются от самых надёжных
         if !self.no_cache {
-            if let Some(cached) = self.cache.lookup(&self.error_signature)? {
-                match self.verify_fix(&cached).await? {
-                    VerificationResult::Success => {
-                        fs::write(self.file_path, cached).await?;
-                        println!("    -> Applied solution from local knowledge cache.");
-                        return Ok(());
-                    }
-                    VerificationResult::Failure(msg) => {
-                        println!("    -> Cached solution failed verification: {}", first_line(&msg));
-                    }
+            for cached in self.cache.lookup(&self.error_signature)? {
+                if self.replay_cached(&cached, &original_code, &mut rejected).await? {
+                    println!("    -> Applied solution from local knowledge cache.");
+                    return Ok(());
                 }
             }
         }
 
+        // Похожие, но не совпадающие ошибки из кэша — примеры для модели
+        let examples = if self.no_cache { String::new() } else { self.similar_fixes(&original_code)? };
+
         // 3) Генерация фикса + самокоррекции на основе подробной ошибки
         let mut last_error_context = self.error_message.to_string();
         for attempt in 1..=MAX_ATTEMPTS {
             println!("    -> Fix attempt {} of {}", attempt, MAX_ATTEMPTS);
-            let suggestion = self.generate_code_suggestion(&original_code, &last_error_context).await?;
+            let suggestion = match self.generate_code_suggestion(&original_code, &last_error_context, &examples, &rejected).await {
+                Ok(set) => set,
+                Err(e) if e.is::<HunkRejected>() => {
+                    println!("    -> Patch did not apply cleanly: {}", first_line(&e.to_string()));
+                    if attempt == MAX_ATTEMPTS {
+                        bail!("Fix failed after {} attempts.", MAX_ATTEMPTS);
+                    }
+                    last_error_context = format!(
+                        "{}\n\nYOUR PREVIOUS EDIT BLOCKS WERE REJECTED: {e}",
+                        self.error_message
+                    );
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
             match self.verify_fix(&suggestion).await? {
                 VerificationResult::Success => {
                     println!("    -> Verification successful!");
-                    if !self.no_cache {
-                        self.cache.store(&self.error_signature, &suggestion)?;
+                    if let Some(applied) = self.apply_reviewed(suggestion, &mut rejected).await? {
+                        // правка пакета ошибок не подходит под сигнатуру одной ошибки
+                        let single_error = self.error_check.as_ref().is_none_or(|c| c.target_count() == 1);
+                        if !self.no_cache && single_error {
+                            self.store_in_cache(&applied)?;
+                        }
+                        return Ok(());
+                    }
+                    if attempt == MAX_ATTEMPTS {
+                        bail!("All {} proposed fixes were rejected.", MAX_ATTEMPTS);
                     }
-                    fs::write(self.file_path, suggestion).await?;
-                    return Ok(());
                 }
                 VerificationResult::Failure(new_err) => {
                     println!("    -> Verification failed: {}", first_line(&new_err));
@@ -90,141 +185,482 @@ impl<'a> PatchEngine<'a> {
         Ok(())
     }
 
-    async fn generate_code_suggestion(&self, original_code: &str, error_context: &str) -> Result<String> {
+    /// Детерминированная попытка: проверяет готовую правку (подсказки rustc) и применяет её,
+    /// не обращаясь к LLM. Ok(false) — правка не прошла проверку или отклонена.
+    pub async fn try_suggested_fix(&self, set: PatchSet) -> Result<bool> {
+        println!("    -> Trying the fix suggested by rustc (no LLM)...");
+        match self.verify_fix(&set).await? {
+            VerificationResult::Success => {
+                let mut rejected = Vec::new();
+                let applied = self.apply_reviewed(set, &mut rejected).await?.is_some();
+                if applied {
+                    println!("    -> Applied rustc's suggested fix.");
+                }
+                Ok(applied)
+            }
+            VerificationResult::Failure(msg) => {
+                println!("    -> rustc's suggestion did not verify: {}", first_line(&msg));
+                Ok(false)
+            }
+        }
+    }
+
+    /// Показывает проверенную правку на ревью (в интерактивном режиме) и применяет её.
+    /// Возвращает применённый набор правок или None, если пользователь его отклонил;
+    /// причина отказа попадает в `rejected`.
+    async fn apply_reviewed(&self, set: PatchSet, rejected: &mut Vec<String>) -> Result<Option<PatchSet>> {
+        let patch = set.unified_diff();
+        match self.changes.review(set).await? {
+            Review::Accept(set) => {
+                self.changes.apply(&set).await?;
+                Ok(Some(set))
+            }
+            // Правку пользователя проверяем заново: в кэш и в проект попадает только то,
+            // что собирается без исходной ошибки.
+            Review::Edited(set) => match self.verify_fix(&set).await? {
+                VerificationResult::Success => {
+                    self.changes.apply(&set).await?;
+                    Ok(Some(set))
+                }
+                VerificationResult::Failure(msg) => {
+                    println!("    -> Edited fix failed verification: {}", first_line(&msg));
+                    rejected.push(format!("Reviewer's edit did not verify: {}\n{}", first_line(&msg), set.unified_diff()));
+                    Ok(None)
+                }
+            },
+            Review::Reject(reason) => {
+                println!("    -> Fix rejected by user.");
+                let reason = if reason.is_empty() { "no reason given".to_string() } else { reason };
+                rejected.push(format!("Reviewer's reason: {reason}\n{patch}"));
+                Ok(None)
+            }
+        }
+    }
+
+    /// Накладывает патч из кэша на текущие файлы и проверяет его так же, как ответ модели.
+    /// Возвращает true, если правка применена; исход записывается в счётчики решения.
+    async fn replay_cached(&self, cached: &CachedFix, original_code: &str, rejected: &mut Vec<String>) -> Result<bool> {
+        let hunks = span_patch::parse_blocks(&cached.patch);
+        let set = match span_patch::reanchor_set(self.file_path, original_code, &hunks, self.focus_line) {
+            Ok(set) => set,
+            Err(e) => {
+                println!("    -> Cached patch does not fit the current code: {}", first_line(&e.to_string()));
+                self.cache.record_outcome(cached, false)?;
+                return Ok(false);
+            }
+        };
+        match self.verify_fix(&set).await? {
+            VerificationResult::Success => {
+                let applied = self.apply_reviewed(set, rejected).await?.is_some();
+                if applied {
+                    self.cache.record_outcome(cached, true)?;
+                }
+                Ok(applied)
+            }
+            VerificationResult::Failure(msg) => {
+                println!("    -> Cached solution failed verification: {}", first_line(&msg));
+                self.cache.record_outcome(cached, false)?;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Лучшие похожие решения из кэша, оформленные как примеры для промпта.
+    /// Код для сравнения — строки вокруг ошибки (или весь файл, если место неизвестно).
+    fn similar_fixes(&self, original_code: &str) -> Result<String> {
+        let lines: Vec<&str> = original_code.lines().collect();
+        let around = match self.focus_line {
+            Some(line) => {
+                let center = line.saturating_sub(1);
+                let start = center.saturating_sub(SIMILARITY_RADIUS);
+                lines[start.min(lines.len())..(center + SIMILARITY_RADIUS + 1).min(lines.len())].join("\n")
+            }
+            None => original_code.to_string(),
+        };
+        let similar = self.cache.similar(&self.error_signature, &around, FEW_SHOT_EXAMPLES)?;
+        if !similar.is_empty() {
+            println!("    -> Using {} similar past fix(es) from the knowledge cache as examples.", similar.len());
+        }
+        Ok(knowledge_cache::describe_examples(&similar))
+    }
+
+    /// Сохраняет в кэш не файлы, а минимальный патч: основной файл — без `FILE:`,
+    /// чтобы патч можно было наложить на файл с той же ошибкой под другим путём.
+    fn store_in_cache(&self, applied: &PatchSet) -> Result<()> {
+        let mut hunks = Vec::new();
+        for (path, edit) in &applied.files {
+            let file = (path != self.file_path).then_some(path.as_str());
+            hunks.extend(span_patch::diff_hunks(file, edit.before.as_deref().unwrap_or_default(), &edit.after));
+        }
+        if hunks.is_empty() {
+            return Ok(());
+        }
+        let crate_versions = Workspace::load(Path::new("."))
+            .ok()
+            .and_then(|ws| ws.package_for_file(Path::new(self.file_path)).map(|p| ws.dependency_versions(p)))
+            .unwrap_or_default();
+        self.cache.store(&self.error_signature, &span_patch::format_blocks(&hunks), &crate_versions, &self.llm.model())?;
+        Ok(())
+    }
+
+    /// Возвращает набор правок (основной файл и, возможно, другие). Если блоки правок
+    /// не применились, ошибка — `HunkRejected`, её текст уходит модели на следующей попытке.
+    async fn generate_code_suggestion(&self, original_code: &str, error_context: &str, examples: &str, rejected: &[String]) -> Result<PatchSet> {
         // Передаем ВЕСЬ контекст ошибки (последний провал проверки), чтобы LLM чётко понимал расхождение типов и место
-        self.llm.generate_full_fix(error_context, original_code, self.web_context).await
+        let with_hint;
+        let error_context = if self.strategy_hint.is_empty() {
+            error_context
+        } else {
+            with_hint = format!("{error_context}\n--- FIX STRATEGY ---\n{}", self.strategy_hint);
+            &with_hint
+        };
+        if self.full_file {
+            let code = self.llm.generate_full_fix(error_context, original_code, self.project_context, examples, self.web_context, rejected).await?;
+            return Ok(PatchSet::single(self.file_path, Some(original_code.to_string()), code));
+        }
+        let focus_lines: Vec<usize> = if self.batch_lines.is_empty() {
+            self.focus_line.into_iter().collect()
+        } else {
+            self.batch_lines.clone()
+        };
+        let excerpt = span_patch::numbered_excerpt(original_code, &focus_lines, EXCERPT_RADIUS);
+        let raw = self.llm
+            .generate_span_fix(error_context, self.file_path, &excerpt, self.project_context, examples, self.web_context, rejected)
+            .await?;
+        let hunks = span_patch::parse_blocks(&raw);
+        Ok(span_patch::apply_set(self.file_path, original_code, &hunks, self.focus_line)?)
     }
 
-    async fn verify_fix(&self, new_code: &str) -> Result<VerificationResult> {
-        match self.verify_in_temp(new_code).await? {
+    async fn verify_fix(&self, set: &PatchSet) -> Result<VerificationResult> {
+        match self.verify_in_temp(set).await? {
             None => Ok(VerificationResult::Success),
             Some(err) => Ok(VerificationResult::Failure(err)),
         }
     }
 
     /// Возвращает None, если всё ок; иначе Some(подробное сообщение об ошибке)
-    async fn verify_in_temp(&self, new_code: &str) -> Result<Option<String>> {
+    async fn verify_in_temp(&self, set: &PatchSet) -> Result<Option<String>> {
+        // Запрет на правку тестов проверяется до сборки: это дёшево и однозначно
+        if let Some(err) = self.test_check.as_ref().and_then(|c| c.guard(set)) {
+            return Ok(Some(err));
+        }
+
         // Создаём временную копию репозитория и запускаем там проверки
-        let temp = TempDir::new().context("Failed to create temp dir")?;
-        copy_dir_all(".", temp.path()).await?;
-
-        // Перезаписываем только целевой файл
-        let dst_file = temp.path().join(self.file_path);
-        if let Some(parent) = dst_file.parent() { fs::create_dir_all(parent).await.ok(); }
-        fs::write(&dst_file, new_code).await?;
-
-        // Общий раннер cargo c парсингом ошибок
-        let mut collect_first_error = |what: &str| -> Result<Option<String>> {
-            let mut child = Command::new("cargo")
-                .current_dir(temp.path())
-                .args([what, "--message-format=json"])
-                .stdout(Stdio::piped())
-                .stderr(Stdio::piped())
-                .spawn()
-                .with_context(|| format!("spawn cargo {what}"))?;
-
-            let messages: Arc<Mutex<Vec<CompilerMessage>>> = Arc::new(Mutex::new(Vec::new()));
-            let messages_out = Arc::clone(&messages);
-            let messages_err = Arc::clone(&messages);
-
-            let mut threads = Vec::new();
-
-            if let Some(stdout) = child.stdout.take() {
-                threads.push(thread::spawn(move || {
-                    let reader = BufReader::new(stdout);
-                    for line in reader.lines().flatten() {
-                        if let Ok(msg) = serde_json::from_str::<CargoMessage>(&line) {
-                            if msg.reason == "compiler-message" {
-                                if let Some(cm) = msg.message {
-                                    messages_out.lock().unwrap().push(cm);
-                                }
-                            }
-                        }
-                    }
-                }));
-            }
-            if let Some(stderr) = child.stderr.take() {
-                threads.push(thread::spawn(move || {
-                    let reader = BufReader::new(stderr);
-                    for line in reader.lines().flatten() {
-                        if let Ok(msg) = serde_json::from_str::<CargoMessage>(&line) {
-                            if msg.reason == "compiler-message" {
-                                if let Some(cm) = msg.message {
-                                    messages_err.lock().unwrap().push(cm);
-                                }
-                            }
-                        }
-                    }
-                }));
-            }
-
-            for t in threads { t.join().unwrap(); }
-            let _status = child.wait()?;
-            let all = Arc::try_unwrap(messages).unwrap().into_inner().unwrap();
-
-            // выбираем ПЕРВУЮ ошибку и формируем понятный текст
-            let mut errors: Vec<CompilerMessage> = all.into_iter().filter(|m| m.level == "error").collect();
-            if errors.is_empty() {
-                return Ok(None);
-            }
-            // отсортируем по строке первого спана
-            errors.sort_by_key(|m| m.spans.first().map_or(usize::MAX, |s| s.line_start));
-            let e = &errors[0];
-            let loc = e.spans.first().map(|s| format!("{}:{}",
-                s.file_name.replace('/', std::path::MAIN_SEPARATOR_STR),
-                s.line_start
-            )).unwrap_or_else(|| "<unknown>".into());
-            let code = e.code.as_ref().map(|c| format!(" [{}]", c.code)).unwrap_or_default();
-            let msg = format!("{}{} at {}\n{}", e.message, code, loc, stringify_spans(e));
-            Ok(Some(msg))
-        };
+        let temp = ScratchCopy::create(Path::new(".")).await?;
+
+        temp.write_set(set).await?;
 
-        // 1) cargo check
-        if let Some(err) = collect_first_error("check")? {
+        // 1) cargo check: ошибки, а в проходе по предупреждениям — ещё и сами предупреждения
+        let command: Vec<&str> = match self.check_command {
+            [] => vec!["check"],
+            command => command.iter().map(String::as_str).collect(),
+        };
+        let messages = run_cargo_in(temp.path(), &command)?;
+        if let Some(check) = &self.error_check {
+            match check.evaluate(&messages) {
+                Ok(fixed) if check.target_count() > 1 => {
+                    println!("    -> Fix removes {} of {} targeted errors.", fixed, check.target_count());
+                }
+                Ok(_) => {}
+                Err(err) => return Ok(Some(err)),
+            }
+        } else if let Some(err) = describe_first_error(&messages) {
             return Ok(Some(err));
         }
+        if let Some(check) = &self.warning_check {
+            if let Some(err) = check.evaluate(&messages) {
+                return Ok(Some(err));
+            }
+        }
 
         // 2) cargo test (если тесты падают — это тоже контекст для LLM)
-        if let Some(err) = collect_first_error("test")? {
+        let messages = run_cargo_in(temp.path(), &["test"])?;
+        let test_error = match &self.error_check {
+            Some(check) => check.new_error(&messages),
+            None => describe_first_error(&messages),
+        };
+        if let Some(err) = test_error {
             return Ok(Some(err));
         }
 
+        // 3) В режиме тестов — результаты самих тестов, а не только диагностика компилятора
+        if let Some(check) = &self.test_check {
+            let run = test_runner::run_tests(temp.path())?;
+            if let Some(err) = check.evaluate(&run) {
+                return Ok(Some(err));
+            }
+        }
+
         Ok(None)
     }
 }
 
-fn stringify_spans(e: &CompilerMessage) -> String {
+/// Условия успеха при исправлении упавшего теста: он проходит, ранее проходившие тесты
+/// не падают, а правка не трогает ни функцию теста, ни утверждения, ни файлы в tests/.
+pub struct TestCheck {
+    pub target: TestFailure,
+    /// Ключи тестов, падавших до правки.
+    pub baseline: HashSet<String>,
+    /// Файл с функцией теста и её текст до правки.
+    pub test_file: Option<String>,
+    pub test_source: Option<String>,
+}
+
+impl TestCheck {
+    fn guard(&self, set: &PatchSet) -> Option<String> {
+        for (path, edit) in &set.files {
+            if path.starts_with("tests/") || path.contains("/tests/") {
+                return Some(format!(
+                    "The fix edits the integration test file {path}. Tests are the specification: change the code under test instead."
+                ));
+            }
+            if let Some(line) = edit.before.as_deref().and_then(|before| removed_assertion(before, &edit.after)) {
+                return Some(format!(
+                    "The fix changes or removes the assertion `{line}` in {path}. Do not edit assertions; change the code under test instead."
+                ));
+            }
+        }
+        let (Some(file), Some(source)) = (&self.test_file, &self.test_source) else {
+            return None;
+        };
+        let edit = set.files.get(file)?;
+        let name = self.target.function_name();
+        if project_analyzer::function_source(&edit.after, name).as_deref() != Some(source.as_str()) {
+            return Some(format!(
+                "The fix modifies the test `{name}` itself. Keep the test unchanged and fix the code under test."
+            ));
+        }
+        None
+    }
+
+    fn evaluate(&self, run: &TestRun) -> Option<String> {
+        let key = self.target.key();
+        if !run.passed.contains(&key) {
+            return Some(match run.failures.iter().find(|f| f.key() == key) {
+                Some(failure) => format!("The test still fails.\n{}", failure.describe()),
+                None => format!("The test `{}` did not run; the tests may not compile.", self.target.name),
+            });
+        }
+        run.failures
+            .iter()
+            .find(|f| !self.baseline.contains(&f.key()))
+            .map(|f| format!("The fix breaks another test.\n{}", f.describe()))
+    }
+}
+
+/// Первая строка с утверждением (`assert!`, `assert_eq!`, ...), которой стало меньше после правки.
+fn removed_assertion(before: &str, after: &str) -> Option<String> {
+    let assertions = |text: &str| -> HashMap<String, usize> {
+        let mut counts = HashMap::new();
+        for line in text.lines().map(str::trim).filter(|l| l.contains("assert") && l.contains("!(")) {
+            *counts.entry(line.to_string()).or_insert(0) += 1;
+        }
+        counts
+    };
+    let kept = assertions(after);
+    let mut removed: Vec<(String, usize)> = assertions(before)
+        .into_iter()
+        .filter(|(line, n)| kept.get(line).copied().unwrap_or(0) < *n)
+        .collect();
+    removed.sort();
+    removed.into_iter().next().map(|(line, _)| line)
+}
+
+/// Условия успеха при исправлении ошибок (одной или пакета в одном файле):
+/// хотя бы одна целевая ошибка исчезла, а новых, которых не было до правки, не появилось.
+/// Ошибки считаются по `issue_key` с кратностью: одинаковые сообщения в одном файле различимы только числом.
+pub struct ErrorCheck {
+    targets: HashMap<String, usize>,
+    baseline: HashMap<String, usize>,
+    lines: Vec<usize>,
+}
+
+impl ErrorCheck {
+    pub fn new(targets: &[CompilerMessage], baseline: &[CompilerMessage]) -> Self {
+        Self {
+            targets: count_errors(targets),
+            baseline: count_errors(baseline),
+            lines: targets.iter().filter_map(|t| t.primary_span().map(|s| s.line_start)).collect(),
+        }
+    }
+
+    pub fn target_count(&self) -> usize {
+        self.targets.values().sum()
+    }
+
+    /// Ok(число исправленных целевых ошибок) или текст для модели, почему правка не годится.
+    pub fn evaluate(&self, messages: &[CompilerMessage]) -> Result<usize, String> {
+        let after = count_errors(messages);
+        if let Some(err) = self.first_new(messages, |key| after[key] > self.baseline.get(key).copied().unwrap_or(0)) {
+            return Err(err);
+        }
+        let fixed: usize = self
+            .targets
+            .iter()
+            .map(|(key, &n)| {
+                let before = self.baseline.get(key).copied().unwrap_or(n);
+                let removed = before.saturating_sub(after.get(key).copied().unwrap_or(0));
+                removed.min(n)
+            })
+            .sum();
+        if fixed > 0 {
+            return Ok(fixed);
+        }
+        let remaining = messages
+            .iter()
+            .filter(|m| is_counted_error(m) && self.targets.contains_key(&issue_key(m)))
+            .map(describe_message)
+            .next()
+            .unwrap_or_default();
+        Err(format!("None of the {} targeted errors went away: {remaining}", self.target_count()))
+    }
+
+    /// Для `cargo test`: тестовые цели пересобирают те же файлы, поэтому кратности там
+    /// другие — проверяем только ошибки, которых не было до правки вовсе.
+    fn new_error(&self, messages: &[CompilerMessage]) -> Option<String> {
+        self.first_new(messages, |key| !self.baseline.contains_key(key))
+    }
+
+    fn first_new(&self, messages: &[CompilerMessage], is_new: impl Fn(&String) -> bool) -> Option<String> {
+        messages
+            .iter()
+            .filter(|m| is_counted_error(m))
+            .find(|m| is_new(&issue_key(m)))
+            .map(|m| format!("The fix introduced a new error: {}", describe_message(m)))
+    }
+}
+
+/// Итоговое «aborting due to N previous errors» меняется с числом ошибок и не считается.
+fn is_counted_error(message: &CompilerMessage) -> bool {
+    message.level == "error" && !message.message.starts_with("aborting due to")
+}
+
+fn count_errors(messages: &[CompilerMessage]) -> HashMap<String, usize> {
+    let mut counts = HashMap::new();
+    for m in messages.iter().filter(|m| is_counted_error(m)) {
+        *counts.entry(issue_key(m)).or_insert(0) += 1;
+    }
+    counts
+}
+
+/// Условия успеха для прохода по предупреждениям: целевые предупреждения исчезли,
+/// а новых (которых не было до правки) не появилось. Считаем по кратностям, как `ErrorCheck`:
+/// у многих линтов (`unused_mut`) одинаковый текст для каждого вхождения, и исправление
+/// одного из них оставляет «то же самое» предупреждение у соседей.
+pub struct WarningCheck {
+    /// Сколько вхождений каждого ключа правка должна убрать.
+    targets: HashMap<String, usize>,
+    baseline: HashMap<String, usize>,
+}
+
+impl WarningCheck {
+    pub fn new(targets: &[CompilerMessage], baseline: &[CompilerMessage]) -> Self {
+        Self { targets: count_warnings(targets), baseline: count_warnings(baseline) }
+    }
+
+    fn evaluate(&self, messages: &[CompilerMessage]) -> Option<String> {
+        let after = count_warnings(messages);
+        let before = |key: &String| self.baseline.get(key).copied().unwrap_or(0);
+        let warnings = messages.iter().filter(|m| is_counted_warning(m));
+        for w in warnings.clone() {
+            let key = issue_key(w);
+            let allowed = before(&key).saturating_sub(self.targets.get(&key).copied().unwrap_or(0));
+            if after[&key] > allowed {
+                return Some(if self.targets.contains_key(&key) {
+                    format!("The warning is still present: {}", describe_message(w))
+                } else {
+                    format!("The fix introduced a new warning: {}", describe_message(w))
+                });
+            }
+        }
+        None
+    }
+}
+
+fn is_counted_warning(message: &CompilerMessage) -> bool {
+    message.level == "warning" && !message.spans.is_empty()
+}
+
+fn count_warnings(messages: &[CompilerMessage]) -> HashMap<String, usize> {
+    let mut counts = HashMap::new();
+    for m in messages.iter().filter(|m| is_counted_warning(m)) {
+        *counts.entry(issue_key(m)).or_insert(0) += 1;
+    }
+    counts
+}
+
+/// Выбирает ПЕРВУЮ ошибку и формирует понятный текст для LLM.
+fn describe_first_error(messages: &[CompilerMessage]) -> Option<String> {
+    let mut errors: Vec<&CompilerMessage> = messages.iter().filter(|m| m.level == "error").collect();
+    // отсортируем по строке основного спана
+    errors.sort_by_key(|m| m.primary_span().map_or(usize::MAX, |s| s.line_start));
+    errors.first().map(|e| describe_message(e))
+}
+
+/// Текст диагностики для промпта: `prompt_text` уже содержит сообщение и код,
+/// место добавляем, только если его там нет (например, у сбоев build-скриптов).
+fn describe_message(e: &CompilerMessage) -> String {
+    let text = e.prompt_text();
+    match e.primary_span() {
+        Some(s) if !text.contains(&format!("{}:{}", s.file_name, s.line_start)) => {
+            let file = s.file_name.replace('/', std::path::MAIN_SEPARATOR_STR);
+            format!("{text}\nLocation: {file}:{}", s.line_start)
+        }
+        _ => text,
+    }
+}
+
+/// Структурированное описание спанов и дочерних help/note (когда нет `rendered`).
+pub fn stringify_spans(e: &CompilerMessage) -> String {
     let mut out = String::new();
     for s in &e.spans {
-        out.push_str(&format!(
-            "- at {}:{}{}\n",
+        let mut line = format!(
+            "- at {}:{}:{}",
             s.file_name.replace('/', std::path::MAIN_SEPARATOR_STR),
             s.line_start,
-            s.suggested_replacement.as_ref().map(|r| format!(" (suggested: {})", r)).unwrap_or_default()
-        ));
-    }
-    out
-}
+            s.column_start
+        );
+        if s.line_end > s.line_start {
+            line.push_str(&format!("-{}:{}", s.line_end, s.column_end));
+        }
+        if s.is_primary {
+            line.push_str(" (primary)");
+        }
+        if let Some(label) = &s.label {
+            line.push_str(&format!(": {label}"));
+        }
+        if let Some(r) = &s.suggested_replacement {
+            let applicability = s.suggestion_applicability
+                .map(|a| format!(", {a:?}"))
+                .unwrap_or_default();
+            line.push_str(&format!(" (suggested: `{r}`{applicability})"));
+        }
+        if let Some(exp) = &s.expansion {
+            line.push_str(&format!(
+                " (in macro `{}` invoked at {}:{}",
+                exp.macro_decl_name, exp.span.file_name, exp.span.line_start
+            ));
+            if let Some(def) = &exp.def_site_span {
+                line.push_str(&format!(", defined at {}:{}", def.file_name, def.line_start));
+            }
+            line.push(')');
+        }
+        out.push_str(&line);
+        out.push('\n');
 
-async fn copy_dir_all(src: impl AsRef<Path>, dst: impl AsRef<Path>) -> std::io::Result<()> {
-    fs::create_dir_all(&dst).await?;
-    for entry in WalkDir::new(src.as_ref())
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| !e.path().to_string_lossy().contains("target")
-              && !e.path().to_string_lossy().contains(".git")
-              && !e.path().to_string_lossy().contains(".rusty_fixer_cache.db"))
-    {
-        let relative = entry.path().strip_prefix(src.as_ref()).unwrap();
-        let dst_path = dst.as_ref().join(relative);
-        if entry.file_type().is_dir() {
-            fs::create_dir_all(&dst_path).await?;
-        } else {
-            if let Some(parent) = dst_path.parent() { fs::create_dir_all(parent).await?; }
-            tokio::fs::copy(entry.path(), &dst_path).await?;
+        if s.is_primary {
+            for text in &s.text {
+                let start = text.highlight_start.saturating_sub(1);
+                let width = text.highlight_end.saturating_sub(text.highlight_start).max(1);
+                out.push_str(&format!("    | {}\n    | {}{}\n", text.text, " ".repeat(start), "^".repeat(width)));
+            }
         }
     }
-    Ok(())
+    for child in &e.children {
+        out.push_str(&format!("{}: {}\n", child.level, child.message));
+        out.push_str(&stringify_spans(child));
+    }
+    out
 }
 
 fn first_line(s: &str) -> String {
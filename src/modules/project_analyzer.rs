@@ -1,12 +1,45 @@
+use crate::CompilerMessage;
+use super::symbol_index::{Symbol, SymbolIndex, SymbolKind};
 use anyhow::Result;
+use std::collections::hash_map::{Entry, HashMap};
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 use tokio::fs;
-use walkdir::WalkDir;
 
-pub struct ProjectAnalyzer;
+/// Поиск по коду проекта. Индекс символов строится через syn и при каждом запросе сверяется
+/// с mtime известных файлов, поэтому правки агента и пользователя видны сразу. Каталоги
+/// обходятся заново, только если искомого имени в индексе нет (мог появиться новый файл).
+pub struct ProjectAnalyzer {
+    indexes: Mutex<HashMap<PathBuf, SymbolIndex>>,
+}
 
 impl ProjectAnalyzer {
-    pub fn new() -> Self { Self }
+    pub fn new() -> Self {
+        Self { indexes: Mutex::new(HashMap::new()) }
+    }
+
+    /// Все определения `name` в проекте (struct, enum, trait, fn, const, макросы, ...),
+    /// сначала типы и трейты.
+    pub fn definitions(&self, name: &str, project_root: &Path) -> Result<Vec<Symbol>> {
+        self.with_index(project_root, &[name.to_string()], |index| index.definitions(name).into_iter().cloned().collect())
+    }
+
+    /// Даёт актуальный индекс проекта; `names` — имена, которые будут в нём искать.
+    fn with_index<R>(&self, project_root: &Path, names: &[String], f: impl FnOnce(&SymbolIndex) -> R) -> Result<R> {
+        let mut indexes = self.indexes.lock().unwrap();
+        let index = match indexes.entry(project_root.to_path_buf()) {
+            Entry::Occupied(entry) => {
+                let index = entry.into_mut();
+                index.refresh_known(project_root)?;
+                if names.iter().any(|name| index.definitions(name).is_empty()) {
+                    index.refresh(project_root)?;
+                }
+                index
+            }
+            Entry::Vacant(entry) => entry.insert(SymbolIndex::load(project_root)?),
+        };
+        Ok(f(index))
+    }
 
     /// Находит определение символа (struct, enum, fn) в проекте.
     /// Возвращает полный путь к файлу и его содержимое.
@@ -15,25 +48,236 @@ impl ProjectAnalyzer {
         symbol_name: &str,
         project_root: &Path,
     ) -> Result<Option<(PathBuf, String)>> {
-        for entry in WalkDir::new(project_root)
-            .into_iter()
-            .filter_map(Result::ok)
-            .filter(|e| !e.path().to_string_lossy().contains("target"))
-            .filter(|e| e.path().is_file() && e.path().extension().map_or(false, |ext| ext == "rs"))
-        {
-            let file_path = entry.path();
-            let content = fs::read_to_string(file_path).await?;
-            let patterns = [
-                format!("struct {}", symbol_name),
-                format!("enum {}", symbol_name),
-                format!("fn {}", symbol_name),
-                format!("trait {}", symbol_name),
-                format!("type {}", symbol_name),
-            ];
-            if patterns.iter().any(|p| content.contains(p)) {
-                return Ok(Some((file_path.to_path_buf(), content)));
-            }
-        }
-        Ok(None)
+        let Some(symbol) = self.definitions(symbol_name, project_root)?.into_iter().next() else {
+            return Ok(None);
+        };
+        let path = project_root.join(&symbol.file);
+        let content = fs::read_to_string(&path).await?;
+        Ok(Some((path, content)))
+    }
+
+    /// Определения символов, упомянутых в диагностике (типы, трейты, функции), — сами элементы
+    /// с атрибутами и сигнатуры их impl-блоков, а не файлы целиком. `exclude_file` — файл,
+    /// который модель и так видит полностью. Пустая строка, если ничего не нашлось.
+    pub async fn related_items(&self, message: &CompilerMessage, project_root: &Path, exclude_file: Option<&str>) -> Result<String> {
+        let names = referenced_symbols(message);
+        // все имена ищем в одном снимке индекса: он сверяется с диском один раз на запрос
+        let lookups = self.with_index(project_root, &names, |index| {
+            names
+                .iter()
+                .map(|name| {
+                    let definition = index.definitions(name).into_iter().next().cloned();
+                    let impls: Vec<Symbol> = index.impls_for(name).into_iter().cloned().collect();
+                    let reexports: Vec<Symbol> = index.reexports(name).into_iter().cloned().collect();
+                    (definition, impls, reexports)
+                })
+                .collect::<Vec<_>>()
+        })?;
+
+        let mut out = String::new();
+        let mut found = 0;
+        let mut sources: HashMap<String, String> = HashMap::new();
+        for (definition, impls, reexports) in lookups {
+            if found == MAX_RELATED_ITEMS || out.len() > MAX_RELATED_CHARS {
+                break;
+            }
+            let Some(definition) = definition.filter(|d| exclude_file != Some(d.file.as_str())) else {
+                continue;
+            };
+
+            for symbol in std::iter::once(&definition).chain(&impls) {
+                if !sources.contains_key(&symbol.file) {
+                    let text = fs::read_to_string(project_root.join(&symbol.file)).await?;
+                    sources.insert(symbol.file.clone(), text);
+                }
+                let lines: Vec<&str> = sources[&symbol.file].lines().collect();
+                let (start, end) = symbol.lines;
+                if start == 0 || end > lines.len() || start > end {
+                    continue;
+                }
+                let text = if symbol.kind == SymbolKind::Impl {
+                    summarize_impl(&lines[start - 1..end])
+                } else {
+                    lines[start - 1..end].join("\n")
+                };
+                let path = if symbol.kind == SymbolKind::Impl {
+                    String::new()
+                } else {
+                    format!(" ({}::{})", symbol.module_path, symbol.name)
+                };
+                out.push_str(&format!("// {}:{start}{path}\n{text}\n", symbol.file));
+            }
+            // путь для `use` важнее места определения, если тип реэкспортирован
+            for reexport in &reexports {
+                out.push_str(&format!(
+                    "// re-exported as {}::{} (pub use {} in {})\n",
+                    reexport.module_path,
+                    reexport.name,
+                    reexport.target.as_deref().unwrap_or_default(),
+                    reexport.file
+                ));
+            }
+            out.push('\n');
+            found += 1;
+        }
+        Ok(out)
+    }
+}
+
+/// Сколько определений и символов текста максимум добавляем к промпту.
+const MAX_RELATED_ITEMS: usize = 6;
+const MAX_RELATED_CHARS: usize = 6000;
+
+/// Ключевые слова и типы std, которые не ищем в проекте.
+const NOT_PROJECT_SYMBOLS: &[&str] = &[
+    "as", "crate", "dyn", "fn", "for", "impl", "in", "let", "mut", "pub", "ref", "self", "Self", "static", "super", "where",
+    "bool", "char", "str", "i8", "i16", "i32", "i64", "i128", "isize", "u8", "u16", "u32", "u64", "u128", "usize", "f32", "f64",
+    "String", "Vec", "Option", "Result", "Box", "Rc", "Arc", "Some", "None", "Ok", "Err", "std", "core", "alloc",
+];
+
+/// Идентификаторы из фрагментов в обратных кавычках (`Foo`, `Vec<Bar>`, `baz`) по порядку появления.
+pub fn referenced_symbols(message: &CompilerMessage) -> Vec<String> {
+    let text = message.full_text();
+    let mut names: Vec<String> = Vec::new();
+    for quoted in text.split('`').skip(1).step_by(2) {
+        for word in quoted.split(|c: char| !(c.is_alphanumeric() || c == '_')) {
+            let is_identifier = word.starts_with(|c: char| c.is_alphabetic() || c == '_') && word.len() > 1;
+            if is_identifier && !NOT_PROJECT_SYMBOLS.contains(&word) && !names.iter().any(|n| n == word) {
+                names.push(word.to_string());
+            }
+        }
+    }
+    names
+}
+
+/// impl-блок в сжатом виде: заголовок, ассоциированные типы и константы, сигнатуры методов без тел.
+fn summarize_impl(lines: &[&str]) -> String {
+    let Some((last, body)) = lines.split_last() else {
+        return String::new();
+    };
+    if body.is_empty() {
+        return last.trim_end().to_string();
+    }
+    let mut summary = Vec::new();
+    let mut header = true;
+    let mut depth = 0usize;
+    for line in body {
+        let trimmed = line.trim_start();
+        let at_depth = depth;
+        for ch in line.split("//").next().unwrap_or("").chars() {
+            match ch {
+                '{' => depth += 1,
+                '}' => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+        }
+        // атрибуты и заголовок до открывающей скобки impl
+        if header {
+            summary.push(line.trim_end().to_string());
+            header = depth == 0;
+            continue;
+        }
+        // только элементы самого impl, а не вложенные в тела методов
+        if at_depth != 1 {
+            continue;
+        }
+        let before_args = trimmed.split('(').next().unwrap_or("");
+        if before_args.split_whitespace().any(|w| w == "fn") {
+            match line.split_once('{') {
+                Some((signature, _)) => summary.push(format!("{} {{ ... }}", signature.trim_end())),
+                None => summary.push(line.trim_end().to_string()),
+            }
+        } else if trimmed.starts_with("type ") || trimmed.starts_with("const ") {
+            summary.push(line.trim_end().to_string());
+        }
+    }
+    summary.push(last.trim_end().to_string());
+    summary.join("\n")
+}
+
+/// Диапазон строк (1-based, включительно) самой внутренней функции, содержащей `line`.
+/// Эвристика по фигурным скобкам: строки и комментарии не разбираются полностью.
+pub fn enclosing_function(source: &str, line: usize) -> Option<(usize, usize)> {
+    let mut open: Vec<(usize, usize)> = Vec::new(); // (строка заголовка, глубина тела)
+    let mut pending: Option<usize> = None;
+    let mut depth = 0usize;
+    for (n, text) in source.lines().enumerate() {
+        let n = n + 1;
+        let code = text.split("//").next().unwrap_or("");
+        if pending.is_none() && code.split(|c: char| !(c.is_alphanumeric() || c == '_')).any(|w| w == "fn") {
+            pending = Some(n);
+        }
+        for ch in code.chars() {
+            match ch {
+                '{' => {
+                    depth += 1;
+                    if let Some(start) = pending.take() {
+                        open.push((start, depth));
+                    }
+                }
+                '}' => {
+                    if open.last().is_some_and(|&(_, d)| d == depth) {
+                        let (start, _) = open.pop()?;
+                        // внутренние функции закрываются раньше внешних
+                        if (start..=n).contains(&line) {
+                            return Some((start, n));
+                        }
+                    }
+                    depth = depth.saturating_sub(1);
+                }
+                // объявление без тела: метод трейта, указатель на функцию
+                ';' => pending = None,
+                _ => {}
+            }
+        }
+    }
+    None
+}
+
+/// Строки (1-based, включительно) функции `name`: от заголовка `fn name(` до закрывающей скобки.
+pub fn function_lines(source: &str, name: &str) -> Option<(usize, usize)> {
+    let header = format!("fn {name}");
+    let line = source.lines().position(|l| {
+        l.split("//").next().unwrap_or("").split(&header).nth(1).is_some_and(|rest| rest.starts_with(['(', '<']))
+    })? + 1;
+    enclosing_function(source, line)
+}
+
+/// Текст функции `name` целиком.
+pub fn function_source(source: &str, name: &str) -> Option<String> {
+    let (start, end) = function_lines(source, name)?;
+    Some(source.lines().skip(start - 1).take(end - start + 1).collect::<Vec<_>>().join("\n"))
+}
+
+/// Имена вызываемых функций и методов в фрагменте кода, по порядку первого появления.
+/// Макросы (`assert_eq!`), ключевые слова и конструкторы вариантов пропускаются.
+pub fn called_functions(code: &str) -> Vec<String> {
+    const SKIP: &[&str] = &["fn", "if", "while", "for", "match", "return", "loop", "Some", "Ok", "Err", "Box", "Vec", "String"];
+    let chars: Vec<char> = code.chars().collect();
+    let mut names: Vec<String> = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i].is_alphabetic() || chars[i] == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let name: String = chars[start..i].iter().collect();
+            let mut j = i;
+            // turbofish: `parse::<u32>(`
+            if chars.get(j) == Some(&':') && chars.get(j + 1) == Some(&':') && chars.get(j + 2) == Some(&'<') {
+                while j < chars.len() && chars[j] != '>' {
+                    j += 1;
+                }
+                j += 1;
+            }
+            let is_call = chars.get(j) == Some(&'(');
+            if is_call && !SKIP.contains(&name.as_str()) && !names.contains(&name) {
+                names.push(name);
+            }
+        } else {
+            i += 1;
+        }
     }
+    names
 }
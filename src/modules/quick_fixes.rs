@@ -1,9 +1,16 @@
+use crate::{run_cargo_in, CompilerMessage};
+use super::approval::Review;
+use super::change_set::{ChangeSet, PatchSet};
+use super::patch_engine::ErrorCheck;
+use super::workspace::ScratchCopy;
 use anyhow::{Context, Result};
+use std::path::Path;
 use tokio::fs;
 
 /// Если в файле встречается #[derive(Serialize|Deserialize)] и нет импорта serde,
 /// добавляет строку `use serde::{Serialize, Deserialize};` в начало файла.
-pub async fn ensure_serde_import(file_path: &str) -> Result<bool> {
+/// `issue` и `baseline` нужны для проверки правки, отредактированной пользователем.
+pub async fn ensure_serde_import(file_path: &str, issue: &CompilerMessage, baseline: &[CompilerMessage], changes: &ChangeSet) -> Result<bool> {
     let content = fs::read_to_string(file_path)
         .await
         .with_context(|| format!("Failed to read {}", file_path))?;
@@ -33,8 +40,23 @@ pub async fn ensure_serde_import(file_path: &str) -> Result<bool> {
     lines.insert(insert_at, "".to_string()); // пустая строка для красоты
 
     let new_content = lines.join("\n");
-    fs::write(file_path, new_content).await
-        .with_context(|| format!("Failed to write {}", file_path))?;
+    let set = PatchSet::single(file_path, Some(content), new_content);
+    let set = match changes.review(set).await? {
+        Review::Accept(set) => set,
+        // свою вставку не проверяем, а правку пользователя собираем в копии проекта
+        Review::Edited(set) => {
+            if let Err(reason) = verify_edit(&set, issue, baseline).await? {
+                println!("    -> Edited QuickFix failed verification: {}", reason.lines().next().unwrap_or_default());
+                return Ok(false);
+            }
+            set
+        }
+        Review::Reject(_) => {
+            println!("    -> QuickFix rejected by user.");
+            return Ok(false);
+        }
+    };
+    changes.apply(&set).await?;
 
     // ВНИМАНИЕ: фигурные скобки в форматной строке нужно экранировать как {{ }}
     println!(
@@ -43,3 +65,11 @@ pub async fn ensure_serde_import(file_path: &str) -> Result<bool> {
     );
     Ok(true)
 }
+
+/// Ok(Ok(())), если в копии проекта с правкой ошибка ушла и новых не появилось.
+async fn verify_edit(set: &PatchSet, issue: &CompilerMessage, baseline: &[CompilerMessage]) -> Result<Result<(), String>> {
+    let temp = ScratchCopy::create(Path::new(".")).await?;
+    temp.write_set(set).await?;
+    let messages = run_cargo_in(temp.path(), &["check"])?;
+    Ok(ErrorCheck::new(std::slice::from_ref(issue), baseline).evaluate(&messages).map(|_| ()))
+}
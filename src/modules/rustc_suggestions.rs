@@ -0,0 +1,116 @@
+use crate::{Applicability, CompilerMessage, Span};
+use super::change_set::PatchSet;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Готовая замена от rustc: байтовый диапазон в файле и новый текст.
+#[derive(Debug, Clone)]
+struct Replacement {
+    file: String,
+    byte_start: usize,
+    byte_end: usize,
+    text: String,
+}
+
+/// Собирает подсказки rustc из диагностики и накладывает их на исходники.
+/// Берутся подсказки самой диагностики и первого дочернего help с подходящими спанами:
+/// разные дочерние help обычно — взаимоисключающие альтернативы.
+/// Возвращает None, если применять нечего или замены конфликтуют.
+pub fn build_patch(message: &CompilerMessage, allow_maybe_incorrect: bool) -> Option<PatchSet> {
+    apply_replacements(replacements(message, allow_maybe_incorrect)?)
+}
+
+/// Как `cargo clippy --fix`: подсказки всех диагностик одним набором правок.
+/// Диагностика, чьи замены пересекаются с уже взятыми, пропускается целиком.
+/// Возвращает набор правок и индексы вошедших в него диагностик.
+pub fn build_combined_patch(messages: &[CompilerMessage], allow_maybe_incorrect: bool) -> Option<(PatchSet, Vec<usize>)> {
+    let mut taken: Vec<Replacement> = Vec::new();
+    let mut included = Vec::new();
+    for (index, message) in messages.iter().enumerate() {
+        let Some(candidate) = replacements(message, allow_maybe_incorrect) else {
+            continue;
+        };
+        if !candidate.iter().any(|c| taken.iter().any(|t| t.overlaps(c))) {
+            taken.extend(candidate);
+            included.push(index);
+        }
+    }
+    let set = apply_replacements(taken)?;
+    Some((set, included))
+}
+
+fn replacements(message: &CompilerMessage, allow_maybe_incorrect: bool) -> Option<Vec<Replacement>> {
+    let eligible = |s: &Span| {
+        s.suggested_replacement.is_some()
+            && match s.suggestion_applicability {
+                Some(Applicability::MachineApplicable) => true,
+                Some(Applicability::MaybeIncorrect) => allow_maybe_incorrect,
+                _ => false,
+            }
+    };
+
+    let mut spans: Vec<&Span> = message.spans.iter().filter(|s| eligible(s)).collect();
+    if let Some(child) = message.children.iter().find(|c| c.spans.iter().any(eligible)) {
+        spans.extend(child.spans.iter().filter(|s| eligible(s)));
+    }
+    if spans.is_empty() {
+        return None;
+    }
+
+    let replacements = spans
+        .into_iter()
+        .map(|s| Replacement {
+            file: s.file_name.clone(),
+            byte_start: s.byte_start,
+            byte_end: s.byte_end,
+            text: s.suggested_replacement.clone().unwrap_or_default(),
+        })
+        .collect();
+    Some(replacements)
+}
+
+impl Replacement {
+    /// Вставки (пустой диапазон) в одну точку тоже считаются пересечением: порядок неизвестен.
+    fn overlaps(&self, other: &Replacement) -> bool {
+        let end = |r: &Replacement| r.byte_end.max(r.byte_start + 1);
+        self.file == other.file && self.byte_start < end(other) && other.byte_start < end(self)
+    }
+}
+
+fn apply_replacements(replacements: Vec<Replacement>) -> Option<PatchSet> {
+    let mut by_file: BTreeMap<String, Vec<Replacement>> = BTreeMap::new();
+    for r in replacements {
+        // правим только файлы проекта, а не исходники зависимостей или std
+        if Path::new(&r.file).is_absolute() {
+            return None;
+        }
+        by_file.entry(r.file.clone()).or_default().push(r);
+    }
+
+    let mut set = PatchSet::default();
+    for (file, mut edits) in by_file {
+        let source = std::fs::read_to_string(&file).ok()?;
+        edits.sort_by_key(|r| std::cmp::Reverse(r.byte_start));
+        edits.dedup_by(|a, b| a.byte_start == b.byte_start && a.byte_end == b.byte_end && a.text == b.text);
+
+        let mut after = source.clone();
+        let mut limit = usize::MAX;
+        for r in edits {
+            let in_bounds = r.byte_start <= r.byte_end && r.byte_end <= after.len();
+            let overlaps = r.byte_end > limit;
+            if !in_bounds
+                || overlaps
+                || !after.is_char_boundary(r.byte_start)
+                || !after.is_char_boundary(r.byte_end)
+            {
+                return None;
+            }
+            after.replace_range(r.byte_start..r.byte_end, &r.text);
+            limit = r.byte_start;
+        }
+        if after != source {
+            set.insert(&file, Some(source), after);
+        }
+    }
+    (!set.files.is_empty()).then_some(set)
+}
@@ -0,0 +1,350 @@
+use super::change_set::PatchSet;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Component, Path};
+
+const SEARCH_MARKER: &str = "<<<<<<< SEARCH";
+const DIVIDER_MARKER: &str = "=======";
+const REPLACE_MARKER: &str = ">>>>>>> REPLACE";
+const FILE_PREFIX: &str = "FILE:";
+
+/// Одна точечная правка: строки SEARCH заменяются на строки REPLACE.
+/// `file == None` — правка основного файла (того, где ошибка).
+#[derive(Debug, Clone)]
+pub struct Hunk {
+    pub file: Option<String>,
+    pub search: Vec<String>,
+    pub replace: Vec<String>,
+}
+
+/// Ханк не применился однозначно — вся правка отклоняется.
+#[derive(Debug)]
+pub struct HunkRejected(pub String);
+
+impl fmt::Display for HunkRejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for HunkRejected {}
+
+/// Разбирает ответ модели на блоки SEARCH/REPLACE. Строка `FILE: path` задаёт файл
+/// для следующих блоков. Всё остальное вне блоков игнорируется.
+pub fn parse_blocks(raw: &str) -> Vec<Hunk> {
+    let mut hunks = Vec::new();
+    let mut file: Option<String> = None;
+    let mut lines = raw.lines();
+    while let Some(line) = lines.next() {
+        if let Some(path) = line.trim().strip_prefix(FILE_PREFIX) {
+            let path = path.trim().trim_matches('`');
+            file = (!path.is_empty()).then(|| path.to_string());
+            continue;
+        }
+        if line.trim() != SEARCH_MARKER {
+            continue;
+        }
+        let mut search = Vec::new();
+        let mut replace = Vec::new();
+        let mut in_replace = false;
+        let mut closed = false;
+        for line in lines.by_ref() {
+            match line.trim() {
+                DIVIDER_MARKER if !in_replace => in_replace = true,
+                REPLACE_MARKER => {
+                    closed = true;
+                    break;
+                }
+                _ if in_replace => replace.push(line.to_string()),
+                _ => search.push(line.to_string()),
+            }
+        }
+        if closed && in_replace {
+            hunks.push(Hunk { file: file.clone(), search, replace });
+        }
+    }
+    hunks
+}
+
+/// Применяет ханки ко всем затронутым файлам и собирает из них `PatchSet`.
+/// Ханки без `FILE:` и с путём основного файла относятся к `primary_path`; прочие файлы
+/// читаются с диска. Пустой SEARCH для несуществующего файла означает его создание.
+pub fn apply_set(
+    primary_path: &str,
+    primary_source: &str,
+    hunks: &[Hunk],
+    focus_line: Option<usize>,
+) -> Result<PatchSet, HunkRejected> {
+    if hunks.is_empty() {
+        return Err(HunkRejected("The reply contained no SEARCH/REPLACE blocks.".into()));
+    }
+
+    let primary = normalize_path(primary_path);
+    let mut by_file: BTreeMap<String, Vec<Hunk>> = BTreeMap::new();
+    for hunk in hunks {
+        let path = match &hunk.file {
+            Some(p) => {
+                check_relative_path(p)?;
+                normalize_path(p)
+            }
+            None => primary.clone(),
+        };
+        by_file.entry(path).or_default().push(hunk.clone());
+    }
+
+    let mut set = PatchSet::default();
+    for (path, file_hunks) in by_file {
+        if path == primary {
+            let after = apply(primary_source, &file_hunks, focus_line)?;
+            set.insert(primary_path, Some(primary_source.to_string()), after);
+            continue;
+        }
+        match std::fs::read_to_string(&path) {
+            Ok(source) => {
+                let after = apply(&source, &file_hunks, None)?;
+                set.insert(&path, Some(source), after);
+            }
+            Err(_) => {
+                if file_hunks.iter().any(|h| h.search.iter().any(|l| !l.trim().is_empty())) {
+                    return Err(HunkRejected(format!(
+                        "File {path} does not exist; to create it use an empty SEARCH section."
+                    )));
+                }
+                let mut content = file_hunks
+                    .iter()
+                    .map(|h| h.replace.join("\n"))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                content.push('\n');
+                set.insert(&path, None, content);
+            }
+        }
+    }
+    Ok(set)
+}
+
+/// Строк контекста вокруг изменений в ханках, которые строятся из готовой правки (для кэша).
+const HUNK_CONTEXT: usize = 3;
+
+/// Минимальные ханки SEARCH/REPLACE, превращающие `before` в `after`: изменённые строки
+/// и до `HUNK_CONTEXT` строк контекста с каждой стороны; близкие изменения сливаются.
+pub fn diff_hunks(file: Option<&str>, before: &str, after: &str) -> Vec<Hunk> {
+    // `diff::lines` даёт лишнюю пустую строку после завершающего `\n`; `apply` режет файл через `lines()`
+    let before: Vec<&str> = before.lines().collect();
+    let after: Vec<&str> = after.lines().collect();
+    let ops = diff::slice(&before, &after);
+    let changed: Vec<usize> = (0..ops.len()).filter(|&i| !matches!(ops[i], diff::Result::Both(..))).collect();
+
+    let mut groups: Vec<(usize, usize)> = Vec::new();
+    for &i in &changed {
+        match groups.last_mut() {
+            Some(last) if i - last.1 <= 2 * HUNK_CONTEXT + 1 => last.1 = i,
+            _ => groups.push((i, i)),
+        }
+    }
+
+    groups
+        .into_iter()
+        .map(|(first, last)| {
+            let mut hunk = Hunk { file: file.map(str::to_string), search: Vec::new(), replace: Vec::new() };
+            let end = (last + HUNK_CONTEXT + 1).min(ops.len());
+            for op in &ops[first.saturating_sub(HUNK_CONTEXT)..end] {
+                match op {
+                    diff::Result::Both(line, _) => {
+                        hunk.search.push(line.to_string());
+                        hunk.replace.push(line.to_string());
+                    }
+                    diff::Result::Left(line) => hunk.search.push(line.to_string()),
+                    diff::Result::Right(line) => hunk.replace.push(line.to_string()),
+                }
+            }
+            hunk
+        })
+        .collect()
+}
+
+/// Записывает ханки в том же формате, в каком их присылает модель (его понимает `parse_blocks`).
+pub fn format_blocks(hunks: &[Hunk]) -> String {
+    let mut out = String::new();
+    let mut file: Option<&str> = None;
+    for hunk in hunks {
+        if hunk.file.as_deref() != file {
+            file = hunk.file.as_deref();
+            out.push_str(&format!("{FILE_PREFIX} {}\n", file.unwrap_or_default()));
+        }
+        out.push_str(SEARCH_MARKER);
+        out.push('\n');
+        for line in &hunk.search {
+            out.push_str(line);
+            out.push('\n');
+        }
+        out.push_str(DIVIDER_MARKER);
+        out.push('\n');
+        for line in &hunk.replace {
+            out.push_str(line);
+            out.push('\n');
+        }
+        out.push_str(REPLACE_MARKER);
+        out.push('\n');
+    }
+    out
+}
+
+/// Применяет сохранённые ханки к файлам, которые могли измениться с тех пор: если ханк
+/// не находится целиком, контекст вокруг изменения постепенно урезается (до одной строки SEARCH).
+pub fn reanchor_set(
+    primary_path: &str,
+    primary_source: &str,
+    hunks: &[Hunk],
+    focus_line: Option<usize>,
+) -> Result<PatchSet, HunkRejected> {
+    let mut first_error = None;
+    for trim in 0..=HUNK_CONTEXT {
+        let trimmed: Vec<Hunk> = hunks.iter().map(|h| trim_context(h, trim)).collect();
+        match apply_set(primary_path, primary_source, &trimmed, focus_line) {
+            Ok(set) => return Ok(set),
+            Err(e) => {
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+    Err(first_error.unwrap_or_else(|| HunkRejected("No hunks to apply.".into())))
+}
+
+/// Убирает до `n` строк общего контекста с каждого края ханка, оставляя непустой SEARCH.
+fn trim_context(hunk: &Hunk, n: usize) -> Hunk {
+    let common = |a: &mut dyn Iterator<Item = &String>, b: &mut dyn Iterator<Item = &String>| {
+        a.zip(b).take_while(|(x, y)| x == y).count()
+    };
+    let prefix = common(&mut hunk.search.iter(), &mut hunk.replace.iter());
+    let suffix = common(&mut hunk.search.iter().rev(), &mut hunk.replace.iter().rev());
+    // у чистой вставки SEARCH состоит только из контекста — одну строку оставляем
+    let spare = hunk.search.len().saturating_sub(1);
+    let head = n.min(prefix).min(spare);
+    let tail = n.min(suffix).min(spare - head).min(hunk.replace.len() - head);
+    Hunk {
+        file: hunk.file.clone(),
+        search: hunk.search[head..hunk.search.len() - tail].to_vec(),
+        replace: hunk.replace[head..hunk.replace.len() - tail].to_vec(),
+    }
+}
+
+/// Пути из ответа модели должны оставаться внутри проекта.
+fn check_relative_path(path: &str) -> Result<(), HunkRejected> {
+    let p = Path::new(path);
+    let escapes = p.is_absolute()
+        || p.components().any(|c| matches!(c, Component::ParentDir | Component::Prefix(_)));
+    if escapes {
+        return Err(HunkRejected(format!(
+            "FILE path `{path}` must be relative to the project root and stay inside it."
+        )));
+    }
+    Ok(())
+}
+
+fn normalize_path(path: &str) -> String {
+    path.trim_start_matches("./").replace('\\', "/")
+}
+
+/// Применяет ханки к исходнику по очереди. Каждый SEARCH обязан совпасть ровно
+/// в одном месте (хвостовые пробелы не учитываются); при нескольких совпадениях
+/// выбирается ближайшее к строке ошибки, если оно одно такое.
+pub fn apply(source: &str, hunks: &[Hunk], focus_line: Option<usize>) -> Result<String, HunkRejected> {
+    if hunks.is_empty() {
+        return Err(HunkRejected("The reply contained no SEARCH/REPLACE blocks.".into()));
+    }
+
+    let newline = if source.contains("\r\n") { "\r\n" } else { "\n" };
+    let mut lines: Vec<String> = source.lines().map(|l| l.to_string()).collect();
+
+    for (n, hunk) in hunks.iter().enumerate() {
+        if hunk.search.iter().all(|l| l.trim().is_empty()) {
+            return Err(HunkRejected(format!("Block #{} has an empty SEARCH section.", n + 1)));
+        }
+        let positions = find_matches(&lines, &hunk.search);
+        let at = match positions.as_slice() {
+            [] => {
+                return Err(HunkRejected(format!(
+                    "Block #{} SEARCH text was not found in the file:\n{}",
+                    n + 1,
+                    hunk.search.join("\n")
+                )))
+            }
+            [single] => *single,
+            many => pick_nearest(many, focus_line).ok_or_else(|| {
+                HunkRejected(format!(
+                    "Block #{} SEARCH text matches {} places; add more context lines to make it unique.",
+                    n + 1,
+                    many.len()
+                ))
+            })?,
+        };
+        lines.splice(at..at + hunk.search.len(), hunk.replace.iter().cloned());
+    }
+
+    let mut out = lines.join(newline);
+    if source.ends_with('\n') {
+        out.push_str(newline);
+    }
+    Ok(out)
+}
+
+fn find_matches(lines: &[String], search: &[String]) -> Vec<usize> {
+    if search.len() > lines.len() {
+        return Vec::new();
+    }
+    (0..=lines.len() - search.len())
+        .filter(|&i| {
+            lines[i..i + search.len()]
+                .iter()
+                .zip(search)
+                .all(|(a, b)| a.trim_end() == b.trim_end())
+        })
+        .collect()
+}
+
+/// Индекс совпадения, ближайшего к строке ошибки (1-based), если он единственный.
+fn pick_nearest(positions: &[usize], focus_line: Option<usize>) -> Option<usize> {
+    let focus = focus_line?.saturating_sub(1);
+    let distance = |p: usize| p.abs_diff(focus);
+    let best = positions.iter().map(|&p| distance(p)).min()?;
+    let mut nearest = positions.iter().filter(|&&p| distance(p) == best);
+    let first = *nearest.next()?;
+    nearest.next().is_none().then_some(first)
+}
+
+/// Фрагменты файла вокруг строк ошибок с номерами строк — для промпта.
+/// Близкие окна сливаются, пропуски между ними помечаются `...`; без строк — весь файл.
+pub fn numbered_excerpt(source: &str, focus_lines: &[usize], radius: usize) -> String {
+    let lines: Vec<&str> = source.lines().collect();
+    let mut windows: Vec<(usize, usize)> = focus_lines
+        .iter()
+        .map(|&line| {
+            let center = line.saturating_sub(1);
+            (center.saturating_sub(radius), (center + radius + 1).min(lines.len()))
+        })
+        .collect();
+    if windows.is_empty() {
+        windows.push((0, lines.len()));
+    }
+    windows.sort();
+
+    let mut merged: Vec<(usize, usize)> = Vec::new();
+    for (start, end) in windows {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+
+    let mut out = String::new();
+    for (n, (start, end)) in merged.into_iter().enumerate() {
+        if n > 0 || start > 0 {
+            out.push_str("  ... |\n");
+        }
+        for (i, line) in lines.iter().enumerate().take(end).skip(start) {
+            out.push_str(&format!("{:>5} | {}\n", i + 1, line));
+        }
+    }
+    out
+}
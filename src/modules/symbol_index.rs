@@ -0,0 +1,315 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use syn::spanned::Spanned;
+use walkdir::WalkDir;
+
+/// Файл индекса внутри проекта: в `target/`, чтобы не попадать в git и во временные копии.
+const INDEX_FILE: &str = "target/rusty-fixer/symbols.json";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SymbolKind {
+    Struct,
+    Enum,
+    Union,
+    Trait,
+    /// impl-блок; `name` — тип, для которого он написан.
+    Impl,
+    Function,
+    /// Метод или ассоциированная функция в impl/трейте; `parent` — тип или трейт.
+    Method,
+    Const,
+    Static,
+    TypeAlias,
+    Macro,
+    /// Реэкспорт `pub use a::b::C [as D]`; `name` — видимое имя, `target` — путь.
+    Use,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// Путь модуля, где объявлен символ: `crate::net::http`.
+    pub module_path: String,
+    /// Путь файла относительно корня проекта.
+    pub file: String,
+    /// Строки (1-based, включительно) вместе с атрибутами и doc-комментариями.
+    pub lines: (usize, usize),
+    pub parent: Option<String>,
+    /// Для impl — реализуемый трейт, для реэкспорта — исходный путь.
+    pub target: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct FileEntry {
+    mtime: (u64, u32),
+    symbols: Vec<Symbol>,
+}
+
+/// Индекс символов проекта по результатам разбора каждого `.rs` файла через syn.
+/// Хранится на диске и обновляется по mtime: перечитываются только изменённые файлы.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SymbolIndex {
+    files: HashMap<String, FileEntry>,
+}
+
+impl SymbolIndex {
+    /// Загружает индекс проекта из `target/` (если есть) и приводит его в актуальное состояние.
+    pub fn load(root: &Path) -> Result<Self> {
+        let mut index: SymbolIndex = std::fs::read_to_string(root.join(INDEX_FILE))
+            .ok()
+            .and_then(|text| serde_json::from_str(&text).ok())
+            .unwrap_or_default();
+        index.refresh(root)?;
+        Ok(index)
+    }
+
+    /// Перечитывает изменённые и новые файлы, забывает удалённые; сохраняет индекс, если что-то поменялось.
+    pub fn refresh(&mut self, root: &Path) -> Result<()> {
+        let mut seen = Vec::new();
+        let mut changed = false;
+        for path in rust_files(root) {
+            let rel = path.strip_prefix(root).unwrap_or(&path).to_string_lossy().replace('\\', "/");
+            let Some(mtime) = modified(&path) else {
+                continue;
+            };
+            seen.push(rel.clone());
+            if self.files.get(&rel).is_some_and(|e| e.mtime == mtime) {
+                continue;
+            }
+            self.reparse(rel, &path, mtime)?;
+            changed = true;
+        }
+        let before = self.files.len();
+        self.files.retain(|file, _| seen.contains(file));
+        changed |= self.files.len() != before;
+
+        if changed {
+            self.save(root)?;
+        }
+        Ok(())
+    }
+
+    /// Как `refresh`, но без обхода каталогов: проверяет только уже известные файлы.
+    /// Новые файлы так не находятся — для них нужен полный `refresh`.
+    pub fn refresh_known(&mut self, root: &Path) -> Result<()> {
+        let mut changed = false;
+        let known: Vec<String> = self.files.keys().cloned().collect();
+        for rel in known {
+            let path = root.join(&rel);
+            match modified(&path) {
+                None => {
+                    self.files.remove(&rel);
+                    changed = true;
+                }
+                Some(mtime) if self.files[&rel].mtime != mtime => {
+                    self.reparse(rel, &path, mtime)?;
+                    changed = true;
+                }
+                Some(_) => {}
+            }
+        }
+        if changed {
+            self.save(root)?;
+        }
+        Ok(())
+    }
+
+    fn reparse(&mut self, rel: String, path: &Path, mtime: (u64, u32)) -> Result<()> {
+        let source = std::fs::read_to_string(path).with_context(|| format!("Failed to read {rel}"))?;
+        // Файл с синтаксической ошибкой — обычное дело для нас: оставляем прежние символы
+        let symbols = match syn::parse_file(&source) {
+            Ok(file) => parse_symbols(&file, &rel),
+            Err(_) => self.files.get(&rel).map(|e| e.symbols.clone()).unwrap_or_default(),
+        };
+        self.files.insert(rel, FileEntry { mtime, symbols });
+        Ok(())
+    }
+
+    fn save(&self, root: &Path) -> Result<()> {
+        let path = root.join(INDEX_FILE);
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&path, serde_json::to_string(self)?).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    fn symbols(&self) -> impl Iterator<Item = &Symbol> {
+        let mut files: Vec<&String> = self.files.keys().collect();
+        files.sort();
+        files.into_iter().flat_map(|f| self.files[f].symbols.iter())
+    }
+
+    /// Определения с именем `name` (без impl-блоков и реэкспортов); сначала типы и трейты.
+    pub fn definitions(&self, name: &str) -> Vec<&Symbol> {
+        let mut found: Vec<&Symbol> = self
+            .symbols()
+            .filter(|s| s.name == name && !matches!(s.kind, SymbolKind::Impl | SymbolKind::Use))
+            .collect();
+        found.sort_by_key(|s| !matches!(s.kind, SymbolKind::Struct | SymbolKind::Enum | SymbolKind::Union | SymbolKind::Trait));
+        found
+    }
+
+    /// impl-блоки типа `name`: собственные и реализации трейтов.
+    pub fn impls_for(&self, name: &str) -> Vec<&Symbol> {
+        self.symbols().filter(|s| s.kind == SymbolKind::Impl && s.name == name).collect()
+    }
+
+    /// Реэкспорты, под которыми символ виден снаружи (`pub use inner::Foo as Bar`).
+    pub fn reexports(&self, name: &str) -> Vec<&Symbol> {
+        self.symbols()
+            .filter(|s| s.kind == SymbolKind::Use)
+            .filter(|s| s.name == name || s.target.as_deref().is_some_and(|t| t.rsplit("::").next() == Some(name)))
+            .collect()
+    }
+}
+
+fn rust_files(root: &Path) -> Vec<PathBuf> {
+    WalkDir::new(root)
+        .into_iter()
+        .filter_entry(|e| {
+            let name = e.file_name().to_string_lossy();
+            e.depth() == 0 || !(name == "target" || name.starts_with('.'))
+        })
+        .filter_map(Result::ok)
+        .filter(|e| e.file_type().is_file() && e.path().extension().is_some_and(|ext| ext == "rs"))
+        .map(|e| e.into_path())
+        .collect()
+}
+
+fn modified(path: &Path) -> Option<(u64, u32)> {
+    let since = std::fs::metadata(path).ok()?.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some((since.as_secs(), since.subsec_nanos()))
+}
+
+/// Путь модуля по расположению файла: `src/lib.rs` → `crate`, `src/net/http.rs` → `crate::net::http`.
+/// Для `tests/`, `examples/`, `benches/` и `src/bin/` корнем считается сам файл.
+fn module_path_for(file: &str) -> String {
+    let parts: Vec<&str> = file.trim_end_matches(".rs").split('/').collect();
+    let Some(src) = parts.iter().rposition(|p| *p == "src") else {
+        return "crate".to_string();
+    };
+    let mut path = vec!["crate"];
+    let rest = &parts[src + 1..];
+    if rest.first() == Some(&"bin") {
+        return "crate".to_string();
+    }
+    for (i, part) in rest.iter().enumerate() {
+        let last = i + 1 == rest.len();
+        if last && (*part == "mod" || (i == 0 && (*part == "lib" || *part == "main"))) {
+            break;
+        }
+        path.push(part);
+    }
+    path.join("::")
+}
+
+fn parse_symbols(file: &syn::File, rel: &str) -> Vec<Symbol> {
+    let mut out = Vec::new();
+    collect_items(&file.items, &module_path_for(rel), rel, &mut out);
+    out
+}
+
+fn lines_of(node: &impl Spanned) -> (usize, usize) {
+    let span = node.span();
+    (span.start().line, span.end().line)
+}
+
+fn type_name(ty: &syn::Type) -> Option<String> {
+    match ty {
+        syn::Type::Path(p) => p.path.segments.last().map(|s| s.ident.to_string()),
+        syn::Type::Reference(r) => type_name(&r.elem),
+        _ => None,
+    }
+}
+
+fn path_string(path: &syn::Path) -> String {
+    path.segments.iter().map(|s| s.ident.to_string()).collect::<Vec<_>>().join("::")
+}
+
+fn collect_items(items: &[syn::Item], module: &str, file: &str, out: &mut Vec<Symbol>) {
+    let symbol = |name: String, kind: SymbolKind, lines: (usize, usize)| Symbol {
+        name,
+        kind,
+        module_path: module.to_string(),
+        file: file.to_string(),
+        lines,
+        parent: None,
+        target: None,
+    };
+
+    for item in items {
+        let lines = lines_of(item);
+        match item {
+            syn::Item::Struct(i) => out.push(symbol(i.ident.to_string(), SymbolKind::Struct, lines)),
+            syn::Item::Enum(i) => out.push(symbol(i.ident.to_string(), SymbolKind::Enum, lines)),
+            syn::Item::Union(i) => out.push(symbol(i.ident.to_string(), SymbolKind::Union, lines)),
+            syn::Item::Fn(i) => out.push(symbol(i.sig.ident.to_string(), SymbolKind::Function, lines)),
+            syn::Item::Const(i) => out.push(symbol(i.ident.to_string(), SymbolKind::Const, lines)),
+            syn::Item::Static(i) => out.push(symbol(i.ident.to_string(), SymbolKind::Static, lines)),
+            syn::Item::Type(i) => out.push(symbol(i.ident.to_string(), SymbolKind::TypeAlias, lines)),
+            syn::Item::Macro(i) => {
+                if let Some(ident) = &i.ident {
+                    out.push(symbol(ident.to_string(), SymbolKind::Macro, lines));
+                }
+            }
+            syn::Item::Trait(i) => {
+                let name = i.ident.to_string();
+                out.push(symbol(name.clone(), SymbolKind::Trait, lines));
+                for inner in &i.items {
+                    if let syn::TraitItem::Fn(f) = inner {
+                        let mut method = symbol(f.sig.ident.to_string(), SymbolKind::Method, lines_of(f));
+                        method.parent = Some(name.clone());
+                        out.push(method);
+                    }
+                }
+            }
+            syn::Item::Impl(i) => {
+                let Some(self_ty) = type_name(&i.self_ty) else {
+                    continue;
+                };
+                let mut block = symbol(self_ty.clone(), SymbolKind::Impl, lines);
+                block.target = i.trait_.as_ref().map(|(_, path, _)| path_string(path));
+                out.push(block);
+                for inner in &i.items {
+                    if let syn::ImplItem::Fn(f) = inner {
+                        let mut method = symbol(f.sig.ident.to_string(), SymbolKind::Method, lines_of(f));
+                        method.parent = Some(self_ty.clone());
+                        out.push(method);
+                    }
+                }
+            }
+            syn::Item::Mod(i) => {
+                // `mod foo;` объявлен в другом файле — его символы придут при разборе того файла
+                if let Some((_, content)) = &i.content {
+                    collect_items(content, &format!("{module}::{}", i.ident), file, out);
+                }
+            }
+            syn::Item::Use(i) if !matches!(i.vis, syn::Visibility::Inherited) => {
+                let mut uses = Vec::new();
+                flatten_use(&i.tree, String::new(), &mut uses);
+                for (name, target) in uses {
+                    let mut reexport = symbol(name, SymbolKind::Use, lines);
+                    reexport.target = Some(target);
+                    out.push(reexport);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Разворачивает `use a::{b::C, D as E}` в пары (видимое имя, полный путь); `*` пропускается.
+fn flatten_use(tree: &syn::UseTree, prefix: String, out: &mut Vec<(String, String)>) {
+    let join = |name: &syn::Ident| if prefix.is_empty() { name.to_string() } else { format!("{prefix}::{name}") };
+    match tree {
+        syn::UseTree::Path(p) => flatten_use(&p.tree, join(&p.ident), out),
+        syn::UseTree::Name(n) => out.push((n.ident.to_string(), join(&n.ident))),
+        syn::UseTree::Rename(r) => out.push((r.rename.to_string(), join(&r.ident))),
+        syn::UseTree::Group(g) => g.items.iter().for_each(|t| flatten_use(t, prefix.clone(), out)),
+        syn::UseTree::Glob(_) => {}
+    }
+}
@@ -0,0 +1,173 @@
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::fs::File;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Упавший тест: набор (`src/lib.rs`, `tests/it.rs`, `doc-tests`), имя, текст паники и место.
+#[derive(Debug, Clone)]
+pub struct TestFailure {
+    pub suite: String,
+    pub name: String,
+    pub message: String,
+    pub location: Option<(String, usize)>,
+}
+
+impl TestFailure {
+    /// Имена тестов повторяются между наборами (`it_works` в lib и в tests/), поэтому ключ — с набором.
+    pub fn key(&self) -> String {
+        test_key(&self.suite, &self.name)
+    }
+
+    pub fn is_doc_test(&self) -> bool {
+        self.suite == DOC_TESTS
+    }
+
+    /// Имя функции теста (последний сегмент пути) или, для doc-теста, документируемого элемента.
+    pub fn function_name(&self) -> &str {
+        if self.is_doc_test() {
+            // `src/lib.rs - add (line 3)`, `src/lib.rs - Foo::bar (line 10)`
+            let item = self.name.split(" - ").nth(1).unwrap_or(&self.name);
+            let item = item.split(" (line").next().unwrap_or(item);
+            return item.rsplit("::").next().unwrap_or(item);
+        }
+        self.name.rsplit("::").next().unwrap_or(&self.name)
+    }
+
+    pub fn describe(&self) -> String {
+        let location = self
+            .location
+            .as_ref()
+            .map(|(file, line)| format!(" at {file}:{line}"))
+            .unwrap_or_default();
+        format!("Test `{}` ({}) failed{location}:\n{}", self.name, self.suite, self.message)
+    }
+}
+
+/// Результат `cargo test`: прошедшие тесты (по ключу) и упавшие.
+#[derive(Debug, Default)]
+pub struct TestRun {
+    pub passed: HashSet<String>,
+    pub failures: Vec<TestFailure>,
+}
+
+const DOC_TESTS: &str = "doc-tests";
+
+/// Номер строки в имени doc-теста (`src/lib.rs - add (line 3)`) меняется от правок выше — отбрасываем.
+fn test_key(suite: &str, name: &str) -> String {
+    let name = if suite == DOC_TESTS { name.split(" (line").next().unwrap_or(name) } else { name };
+    format!("{suite}::{name}")
+}
+
+/// Запускает все тесты в `dir` и разбирает текстовый вывод libtest
+/// (`--format json` есть только на nightly). stdout и stderr пишутся в один файл,
+/// чтобы строки `Running ...` cargo шли вперемешку с выводом тестов в исходном порядке.
+pub fn run_tests(dir: &Path) -> Result<TestRun> {
+    let log = tempfile::tempfile().context("Failed to create a temp file for test output")?;
+    Command::new("cargo")
+        .current_dir(dir)
+        .args(["test", "--no-fail-fast", "--color", "never"])
+        .env("RUST_BACKTRACE", "0")
+        .stdout(Stdio::from(log.try_clone()?))
+        .stderr(Stdio::from(log.try_clone()?))
+        .status()
+        .context("Failed to spawn cargo test")?;
+    Ok(parse_output(&read_all(log)?))
+}
+
+fn read_all(mut file: File) -> Result<String> {
+    use std::io::{Read, Seek, SeekFrom};
+    file.seek(SeekFrom::Start(0))?;
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes)?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Разбирает вывод libtest: строки `test NAME ... ok|FAILED`, блоки `---- NAME stdout ----`
+/// с паникой `thread '...' panicked at FILE:LINE:COL:` и заголовки наборов от cargo.
+pub fn parse_output(output: &str) -> TestRun {
+    let mut run = TestRun::default();
+    let mut suite = String::new();
+    let mut failed: Vec<(String, String)> = Vec::new();
+    let mut blocks: Vec<(String, String, Vec<String>)> = Vec::new();
+    let mut current: Option<(String, String, Vec<String>)> = None;
+
+    for line in output.lines() {
+        let trimmed = line.trim();
+        if let Some(rest) = trimmed.strip_prefix("Running ") {
+            // `Running unittests src/lib.rs (target/...)`, `Running tests/it.rs (target/...)`
+            let source = rest.trim_start_matches("unittests ");
+            suite = source.split(" (").next().unwrap_or(source).to_string();
+            blocks.extend(current.take());
+            continue;
+        }
+        if trimmed.starts_with("Doc-tests ") {
+            suite = DOC_TESTS.to_string();
+            blocks.extend(current.take());
+            continue;
+        }
+        if let Some(rest) = line.strip_prefix("test ") {
+            if let Some(name) = rest.strip_suffix(" ... ok") {
+                run.passed.insert(test_key(&suite, name));
+                continue;
+            }
+            if let Some(name) = rest.strip_suffix(" ... FAILED") {
+                failed.push((suite.clone(), name.to_string()));
+                continue;
+            }
+        }
+        if let Some(name) = line.strip_prefix("---- ").and_then(|r| r.strip_suffix(" stdout ----")) {
+            blocks.extend(current.take());
+            current = Some((suite.clone(), name.to_string(), Vec::new()));
+            continue;
+        }
+        // второй список `failures:` и итог набора закрывают последний блок
+        if line == "failures:" || line.starts_with("test result:") {
+            blocks.extend(current.take());
+            continue;
+        }
+        if let Some((_, _, lines)) = current.as_mut() {
+            lines.push(line.to_string());
+        }
+    }
+    blocks.extend(current);
+
+    for (suite, name) in failed {
+        let block = blocks.iter().find(|(s, n, _)| *s == suite && *n == name).map(|(_, _, l)| l.as_slice());
+        let (message, location) = block.map(parse_panic).unwrap_or_default();
+        run.failures.push(TestFailure { suite, name, message, location });
+    }
+    run
+}
+
+/// Текст паники и её место из блока вывода теста. Понимает формат с Rust 1.73
+/// (`panicked at src/lib.rs:20:9:` и сообщение на следующих строках) и старый
+/// (`panicked at 'msg', src/lib.rs:20:9`).
+fn parse_panic(lines: &[String]) -> (String, Option<(String, usize)>) {
+    let Some(start) = lines.iter().position(|l| l.contains("panicked at ")) else {
+        let text = lines.iter().map(|l| l.trim_end()).filter(|l| !l.is_empty()).collect::<Vec<_>>();
+        return (text.join("\n"), None);
+    };
+    let header = lines[start].split("panicked at ").nth(1).unwrap_or_default();
+
+    if let Some(location) = header.strip_suffix(':') {
+        let message: Vec<&str> = lines[start + 1..]
+            .iter()
+            .map(|l| l.trim_end())
+            .take_while(|l| !l.starts_with("note: ") && !l.starts_with("stack backtrace:"))
+            .filter(|l| !l.is_empty())
+            .collect();
+        return (message.join("\n"), parse_location(location));
+    }
+    match header.rsplit_once(", ") {
+        Some((message, location)) => (message.trim_matches('\'').to_string(), parse_location(location)),
+        None => (header.to_string(), None),
+    }
+}
+
+fn parse_location(location: &str) -> Option<(String, usize)> {
+    let mut parts = location.split(':');
+    let file = parts.next()?.trim_start_matches("./").to_string();
+    let line = parts.next()?.parse().ok()?;
+    Some((file, line))
+}
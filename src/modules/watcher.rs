@@ -0,0 +1,173 @@
+use crate::{run_cargo_and_collect, Agent, CompilerMessage};
+use super::approval::Interrupt;
+use super::change_set::same_file;
+use super::issue_detector::{self, issue_key};
+use anyhow::{Context, Result};
+use colored::*;
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+
+/// Сколько ждём «тишины» после последнего сохранения, прежде чем пересобирать.
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Watch-режим: следим за исходниками и Cargo.toml, после каждой серии сохранений
+/// пересобираем проект и запускаем исправление только для новых ошибок.
+pub async fn run(agent: &Agent<'_>) -> Result<()> {
+    let root = std::env::current_dir()?;
+    let (tx, mut rx) = mpsc::unbounded_channel::<Vec<PathBuf>>();
+
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<Event>| {
+        let Ok(event) = res else { return };
+        if !is_relevant_kind(&event.kind) {
+            return;
+        }
+        let paths: Vec<PathBuf> = event.paths.into_iter().filter(|p| is_watched_path(p)).collect();
+        if !paths.is_empty() {
+            let _ = tx.send(paths);
+        }
+    })
+    .context("Failed to create file watcher")?;
+    watcher
+        .watch(&root, RecursiveMode::Recursive)
+        .with_context(|| format!("Failed to watch {}", root.display()))?;
+
+    println!("{}", "👀 Watch mode enabled. Press Ctrl+C to exit.".cyan().bold());
+
+    let mut known: HashSet<String> = HashSet::new();
+    loop {
+        let mut changed = fix_new_errors(agent, &root, &mut known, &mut rx).await?;
+        if changed.is_empty() {
+            println!("{}", "Waiting for changes...".dimmed());
+            let Some(paths) = rx.recv().await else { break };
+            changed = paths;
+        }
+        let changed = debounce(&mut rx, changed).await;
+        println!("\n{} {}", "Changed:".bold(), describe_paths(&root, &changed));
+    }
+
+    Ok(())
+}
+
+/// Пересобирает проект и по очереди чинит ошибки, которые ещё не пытались исправить.
+/// `known` — ключи ошибок, для которых попытка уже была; ключ остаётся там, пока ошибка
+/// есть в сборке. Возвращает изменения, пришедшие во время исправления и ещё не учтённые сборкой.
+async fn fix_new_errors(
+    agent: &Agent<'_>,
+    root: &Path,
+    known: &mut HashSet<String>,
+    rx: &mut UnboundedReceiver<Vec<PathBuf>>,
+) -> Result<Vec<PathBuf>> {
+    loop {
+        // Сборка блокирующая: уводим её с потока рантайма, чтобы не задерживать события.
+        let (errors, _warnings) = tokio::task::spawn_blocking(|| run_cargo_and_collect(&["build"]))
+            .await?
+            .context("Cargo build failed to execute")?;
+
+        if errors.is_empty() {
+            println!("{}", "✅ No errors found.".green().bold());
+            known.clear();
+            return Ok(Vec::new());
+        }
+
+        // исчезнувшие ошибки забываем: если вернутся, это будет новая ошибка
+        let current: HashSet<String> = errors.iter().map(issue_key).collect();
+        known.retain(|key| current.contains(key));
+        let fresh: Vec<CompilerMessage> = errors
+            .iter()
+            .filter(|e| !known.contains(&issue_key(e)))
+            .cloned()
+            .collect();
+
+        if fresh.is_empty() {
+            println!("{} {}", "No new errors; already attempted:".yellow(), known.len());
+            return Ok(Vec::new());
+        }
+
+        let Some(issue) = issue_detector::prioritize_and_classify(&fresh) else {
+            return Ok(Vec::new());
+        };
+        let key = issue_key(&issue.message);
+        known.insert(key.clone());
+        let target = issue.message.primary_span().map(|s| root.join(&s.file_name));
+
+        let mut changed = Vec::new();
+        let fix = agent.handle_issue(&issue, &errors);
+        tokio::pin!(fix);
+        let outcome = loop {
+            tokio::select! {
+                biased;
+                res = &mut fix => break Some(res),
+                Some(paths) = rx.recv() => {
+                    // События от наших собственных записей отменой не считаются.
+                    let paths: Vec<PathBuf> =
+                        paths.into_iter().filter(|p| !agent.changes.is_own_write(p)).collect();
+                    let hit = target
+                        .as_ref()
+                        .is_some_and(|t| paths.iter().any(|p| same_file(p, t)));
+                    changed.extend(paths);
+                    if hit {
+                        break None;
+                    }
+                }
+            }
+        };
+
+        match outcome {
+            None => {
+                println!("{}", "✋ File edited during fix; cancelling the attempt.".yellow().bold());
+                // Начатая запись правки не прерывается: дожидаемся её, чтобы она попала в патч.
+                if let Err(e) = agent.changes.settle().await {
+                    eprintln!("{} {e:#}", "Failed to write the fix:".red().bold());
+                }
+                // попытку прервали — после правки пользователя ошибку стоит попробовать снова
+                known.remove(&key);
+                return Ok(changed);
+            }
+            Some(Ok(true)) => {
+                // Наши собственные записи тоже приходят событиями: сбрасываем их,
+                // следующая сборка всё равно увидит актуальное состояние файлов.
+                debounce(rx, Vec::new()).await;
+            }
+            // Неудачная попытка остаётся в `known`; переходим к следующей новой ошибке.
+            Some(Ok(false)) => {}
+            Some(Err(e)) if e.downcast_ref::<Interrupt>() == Some(&Interrupt::Quit) => return Err(e),
+            Some(Err(e)) => eprintln!("{} {e:#}", "Fix attempt failed:".red().bold()),
+        }
+    }
+}
+
+/// Собирает события, пока не наступит пауза длиной DEBOUNCE.
+async fn debounce(rx: &mut UnboundedReceiver<Vec<PathBuf>>, mut changed: Vec<PathBuf>) -> Vec<PathBuf> {
+    while let Ok(Some(paths)) = tokio::time::timeout(DEBOUNCE, rx.recv()).await {
+        changed.extend(paths);
+    }
+    changed.sort();
+    changed.dedup();
+    changed
+}
+
+fn is_relevant_kind(kind: &EventKind) -> bool {
+    matches!(kind, EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_))
+}
+
+fn is_watched_path(path: &Path) -> bool {
+    let ignored = path
+        .components()
+        .any(|c| c.as_os_str() == "target" || c.as_os_str() == ".git");
+    if ignored {
+        return false;
+    }
+    path.file_name().is_some_and(|n| n == "Cargo.toml")
+        || path.extension().is_some_and(|ext| ext == "rs")
+}
+
+fn describe_paths(root: &Path, paths: &[PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|p| p.strip_prefix(root).unwrap_or(p).display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -0,0 +1,343 @@
+use super::change_set::PatchSet;
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+use tempfile::TempDir;
+use walkdir::WalkDir;
+
+/// Модель воркспейса из `cargo metadata --format-version 1`: пакеты, цели, фичи,
+/// path-зависимости и разрешённый граф зависимостей.
+#[derive(Debug, Deserialize)]
+pub struct Workspace {
+    pub packages: Vec<Package>,
+    pub workspace_members: Vec<String>,
+    pub workspace_root: PathBuf,
+    pub target_directory: PathBuf,
+    /// Нет при `--no-deps` (например, без сети и без Cargo.lock).
+    #[serde(default)]
+    resolve: Option<Resolve>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Package {
+    pub id: String,
+    pub name: String,
+    pub version: String,
+    /// None — локальный пакет (член воркспейса или path-зависимость).
+    pub source: Option<String>,
+    pub manifest_path: PathBuf,
+    #[serde(default)]
+    pub targets: Vec<Target>,
+    #[serde(default)]
+    pub features: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    pub dependencies: Vec<Dependency>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Target {
+    pub name: String,
+    pub kind: Vec<String>,
+    pub src_path: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Dependency {
+    pub name: String,
+    pub req: String,
+    /// `null` — обычная, `dev` или `build`.
+    pub kind: Option<String>,
+    #[serde(default)]
+    pub optional: bool,
+    #[serde(default)]
+    pub features: Vec<String>,
+    /// Каталог path-зависимости.
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Resolve {
+    nodes: Vec<ResolveNode>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResolveNode {
+    id: String,
+    #[serde(default)]
+    deps: Vec<ResolveDep>,
+    #[serde(default)]
+    features: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResolveDep {
+    name: String,
+    pkg: String,
+}
+
+impl Package {
+    pub fn dir(&self) -> &Path {
+        self.manifest_path.parent().unwrap_or(Path::new(""))
+    }
+
+    pub fn is_local(&self) -> bool {
+        self.source.is_none()
+    }
+}
+
+impl Workspace {
+    /// Запускает `cargo metadata` в `dir`. Если разрешить зависимости не удалось
+    /// (нет сети, нет Cargo.lock), берётся модель без графа (`--no-deps`).
+    pub fn load(dir: &Path) -> Result<Self> {
+        let metadata = |extra: &[&str]| -> Result<Workspace> {
+            let output = Command::new("cargo")
+                .current_dir(dir)
+                .args(["metadata", "--format-version", "1"])
+                .args(extra)
+                .output()
+                .context("Failed to spawn cargo metadata")?;
+            if !output.status.success() {
+                bail!("cargo metadata failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+            }
+            serde_json::from_slice(&output.stdout).context("Failed to parse cargo metadata output")
+        };
+        metadata(&[]).or_else(|_| metadata(&["--no-deps"]))
+    }
+
+    pub fn members(&self) -> impl Iterator<Item = &Package> {
+        self.packages.iter().filter(|p| self.workspace_members.contains(&p.id))
+    }
+
+    pub fn package_by_id(&self, id: &str) -> Option<&Package> {
+        self.packages.iter().find(|p| p.id == id)
+    }
+
+    /// Локальный пакет, которому принадлежит файл: с самым длинным совпадающим каталогом.
+    pub fn package_for_file(&self, file: &Path) -> Option<&Package> {
+        let file = absolute(file);
+        self.packages
+            .iter()
+            .filter(|p| p.is_local() && file.starts_with(p.dir()))
+            .max_by_key(|p| p.dir().components().count())
+    }
+
+    /// Манифест пакета файла относительно текущего каталога (как его ждут CargoExpert и др.).
+    pub fn manifest_for_file(&self, file: &Path) -> Option<String> {
+        self.package_for_file(file).map(|p| relative_to_cwd(&p.manifest_path))
+    }
+
+    /// Каталоги локальных пакетов вне корня воркспейса (`path = "../shared"`):
+    /// без них временная копия не соберётся.
+    pub fn external_path_packages(&self) -> Vec<&Path> {
+        let mut dirs: Vec<&Path> = self
+            .packages
+            .iter()
+            .filter(|p| p.is_local() && !p.dir().starts_with(&self.workspace_root))
+            .map(Package::dir)
+            .collect();
+        dirs.sort();
+        dirs.dedup();
+        dirs
+    }
+
+    /// Описание пакета для промпта: цели, включённые фичи и прямые зависимости
+    /// с разрешёнными версиями и фичами.
+    pub fn describe_package(&self, package: &Package) -> String {
+        let mut out = format!("Package `{}` {} ({})\n", package.name, package.version, relative_to_cwd(&package.manifest_path));
+        let targets: Vec<String> = package
+            .targets
+            .iter()
+            .map(|t| format!("{} `{}` ({})", t.kind.join("/"), t.name, relative_to_cwd(&t.src_path)))
+            .collect();
+        out.push_str(&format!("Targets: {}\n", targets.join(", ")));
+        let others: Vec<&str> = self.members().filter(|m| m.id != package.id).map(|m| m.name.as_str()).collect();
+        if !others.is_empty() {
+            out.push_str(&format!("Other workspace members: {}\n", others.join(", ")));
+        }
+        if !package.features.is_empty() {
+            let available: Vec<&str> = package.features.keys().map(String::as_str).collect();
+            out.push_str(&format!("Declared features: {}\n", available.join(", ")));
+        }
+
+        if let Some(node) = self.node(&package.id).filter(|n| !n.features.is_empty()) {
+            out.push_str(&format!("Enabled features: {}\n", node.features.join(", ")));
+        }
+        let resolved = self.resolved_dependencies(package);
+
+        out.push_str("Dependencies:\n");
+        for dep in &package.dependencies {
+            let section = match dep.kind.as_deref() {
+                Some("dev") => " [dev]",
+                Some("build") => " [build]",
+                _ => "",
+            };
+            let (version, features) = match resolved_package(&resolved, dep) {
+                Some(pkg) => {
+                    let enabled = self.node(&pkg.id);
+                    (pkg.version.clone(), enabled.map(|n| n.features.join(", ")).unwrap_or_default())
+                }
+                None => (dep.req.clone(), dep.features.join(", ")),
+            };
+            let optional = if dep.optional { " optional" } else { "" };
+            let path = dep.path.as_ref().map(|p| format!(" path={}", relative_to_cwd(p))).unwrap_or_default();
+            out.push_str(&format!("  - {} {version}{section}{optional}{path}", dep.name));
+            if !features.is_empty() {
+                out.push_str(&format!(" (features: {features})"));
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Прямые зависимости пакета с разрешёнными версиями (или требованиями из манифеста,
+    /// если графа нет): `serde 1.0.210, tokio 1.40.0`.
+    pub fn dependency_versions(&self, package: &Package) -> String {
+        let resolved = self.resolved_dependencies(package);
+        let mut versions: Vec<String> = package
+            .dependencies
+            .iter()
+            .map(|dep| match resolved_package(&resolved, dep) {
+                Some(pkg) => format!("{} {}", dep.name, pkg.version),
+                None => format!("{} {}", dep.name, dep.req),
+            })
+            .collect();
+        versions.sort();
+        versions.dedup();
+        versions.join(", ")
+    }
+
+    fn node(&self, id: &str) -> Option<&ResolveNode> {
+        self.resolve.as_ref().and_then(|r| r.nodes.iter().find(|n| n.id == id))
+    }
+
+    /// Зависимости пакета из разрешённого графа: имя крейта → пакет.
+    fn resolved_dependencies(&self, package: &Package) -> HashMap<&str, &Package> {
+        self.node(&package.id)
+            .map(|n| n.deps.iter().filter_map(|d| Some((d.name.as_str(), self.package_by_id(&d.pkg)?))).collect())
+            .unwrap_or_default()
+    }
+}
+
+/// В графе имена крейтов с `-` записаны через `_`.
+fn resolved_package<'a>(resolved: &HashMap<&str, &'a Package>, dep: &Dependency) -> Option<&'a Package> {
+    resolved.get(dep.name.replace('-', "_").as_str()).copied()
+}
+
+/// Пути cargo metadata канонические, поэтому и наши пути приводим к каноническому виду.
+fn absolute(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| current_dir().join(path))
+}
+
+fn current_dir() -> PathBuf {
+    let cwd = std::env::current_dir().unwrap_or_default();
+    std::fs::canonicalize(&cwd).unwrap_or(cwd)
+}
+
+/// Путь относительно текущего каталога; соседние каталоги (path-зависимости рядом
+/// с проектом) — через `..`, чтобы промпты не зависели от расположения проекта.
+/// Пути, у которых с текущим каталогом общий только корень, остаются абсолютными.
+pub fn relative_to_cwd(path: &Path) -> String {
+    let cwd = current_dir();
+    let shared = cwd.components().zip(path.components()).take_while(|(a, b)| a == b).count();
+    let rel: PathBuf = if shared > 1 {
+        std::iter::repeat_n(Component::ParentDir, cwd.components().count() - shared)
+            .chain(path.components().skip(shared))
+            .collect()
+    } else {
+        path.to_path_buf()
+    };
+    rel.to_string_lossy().replace('\\', "/")
+}
+
+/// Временная копия воркспейса для проверки правок. Path-зависимости вне корня копируются
+/// рядом с сохранением относительных путей, каталог сборки, `.git` и БД кэша — нет.
+pub struct ScratchCopy {
+    _dir: TempDir,
+    root: PathBuf,
+}
+
+impl ScratchCopy {
+    /// Копирует воркспейс, которому принадлежит `src`. Если `cargo metadata` не работает
+    /// (например, сломан сам манифест), копируется только `src`.
+    pub async fn create(src: &Path) -> Result<Self> {
+        let dir = TempDir::new().context("Failed to create temp dir")?;
+        let src = absolute(src);
+        let Ok(workspace) = Workspace::load(&src) else {
+            copy_tree(&src, dir.path(), &[src.join("target")]).await?;
+            let root = dir.path().to_path_buf();
+            return Ok(Self { _dir: dir, root });
+        };
+
+        let mut roots: Vec<PathBuf> = vec![workspace.workspace_root.clone()];
+        roots.extend(workspace.external_path_packages().into_iter().map(Path::to_path_buf));
+        let ancestor = common_ancestor(&roots);
+        let skip = [workspace.target_directory.clone()];
+        for root in &roots {
+            let dst = dir.path().join(root.strip_prefix(&ancestor).unwrap_or(Path::new("")));
+            copy_tree(root, &dst, &skip).await?;
+        }
+
+        // cargo мог быть запущен из подкаталога воркспейса — сохраняем то же место в копии
+        let offset = src.strip_prefix(&ancestor).unwrap_or(Path::new(""));
+        let root = dir.path().join(offset);
+        Ok(Self { _dir: dir, root })
+    }
+
+    /// Каталог в копии, соответствующий исходному `src`.
+    pub fn path(&self) -> &Path {
+        &self.root
+    }
+
+    /// Накладывает весь набор правок разом: проверяется только их совокупность.
+    pub async fn write_set(&self, set: &PatchSet) -> Result<()> {
+        for (path, edit) in &set.files {
+            let dst = self.root.join(path);
+            if let Some(parent) = dst.parent() {
+                tokio::fs::create_dir_all(parent).await.ok();
+            }
+            tokio::fs::write(&dst, &edit.after).await.with_context(|| format!("Failed to write {}", dst.display()))?;
+        }
+        Ok(())
+    }
+}
+
+fn common_ancestor(paths: &[PathBuf]) -> PathBuf {
+    let mut ancestor: Vec<Component> = paths[0].components().collect();
+    for path in &paths[1..] {
+        let shared = ancestor.iter().zip(path.components()).take_while(|(a, b)| **a == *b).count();
+        ancestor.truncate(shared);
+    }
+    ancestor.iter().collect()
+}
+
+/// Каталоги систем контроля версий: для сборки не нужны, а бывают большими.
+const VCS_DIRS: &[&str] = &[".git", ".hg", ".svn", ".jj"];
+
+/// Копирует дерево `src` в `dst`, пропуская `skip`, каталоги VCS, `target/` пакетов
+/// и БД кэша решений.
+pub async fn copy_tree(src: &Path, dst: &Path, skip: &[PathBuf]) -> std::io::Result<()> {
+    tokio::fs::create_dir_all(dst).await?;
+    let walker = WalkDir::new(src).into_iter().filter_entry(|e| {
+        let name = e.file_name().to_string_lossy();
+        let is_dir = e.file_type().is_dir();
+        // `target` рядом с Cargo.toml — каталог сборки пакета, а не модуль с таким именем
+        let build_dir = is_dir && name == "target" && e.path().with_file_name("Cargo.toml").exists();
+        let vcs_dir = is_dir && VCS_DIRS.contains(&name.as_ref());
+        !(vcs_dir || build_dir || name == ".rusty_fixer_cache.db" || skip.iter().any(|s| e.path() == s))
+    });
+    for entry in walker.filter_map(Result::ok) {
+        let relative = entry.path().strip_prefix(src).unwrap_or(entry.path());
+        let dst_path = dst.join(relative);
+        if entry.file_type().is_dir() {
+            tokio::fs::create_dir_all(&dst_path).await?;
+        } else if entry.file_type().is_file() {
+            if let Some(parent) = dst_path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            tokio::fs::copy(entry.path(), &dst_path).await?;
+        }
+    }
+    Ok(())
+}
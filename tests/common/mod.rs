@@ -0,0 +1,88 @@
+//! Общие помощники интеграционных тестов: фейковый Ollama и запуск бинарника.
+
+#![allow(dead_code)]
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::path::Path;
+use std::process::{Command, Output};
+
+/// Минимальный сервер с API `/api/chat` Ollama; ответ выбирает `reply` по телу запроса.
+pub fn spawn_fake_ollama(reply: fn(&str) -> String) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { continue };
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap_or(0) == 0 || line == "\r\n" {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        length = value.trim().parse().unwrap_or(0);
+                    }
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            let content = reply(&String::from_utf8_lossy(&body));
+            let reply = serde_json::json!({ "message": { "content": content } }).to_string();
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{reply}",
+                reply.len()
+            )
+            .unwrap();
+        }
+    });
+    format!("http://{addr}")
+}
+
+/// Команда запуска бинарника в `dir` с кэшем в `data_home` и без фикстур из окружения.
+pub fn fixer(dir: &Path, data_home: &Path, args: &[&str]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_rusty-fixer-ai"));
+    command
+        .current_dir(dir)
+        .args(args)
+        .env("XDG_DATA_HOME", data_home)
+        .env("RUSTY_FIXER_CACHE_SCOPE", "project")
+        .env_remove("RUSTY_FIXER_LLM_RECORD")
+        .env_remove("RUSTY_FIXER_LLM_REPLAY");
+    command
+}
+
+pub fn run_fixer(dir: &Path, data_home: &Path, args: &[&str]) -> Output {
+    let output = fixer(dir, data_home, args).output().unwrap();
+    assert!(
+        output.status.success(),
+        "rusty-fixer-ai {args:?} failed:\n{}\n{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+/// Воркспейс из бинарника `demo`, который вызывает `helper::extra()`, и библиотеки `helper`,
+/// где эта функция спрятана за фичей `extra`, не включённой в `demo`.
+pub fn write_feature_workspace(dir: &Path) {
+    std::fs::create_dir_all(dir.join("helper/src")).unwrap();
+    std::fs::write(
+        dir.join("helper/Cargo.toml"),
+        "[package]\nname = \"helper\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[features]\nextra = []\n",
+    )
+    .unwrap();
+    std::fs::write(dir.join("helper/src/lib.rs"), "#[cfg(feature = \"extra\")]\npub fn extra() -> i32 {\n    1\n}\n").unwrap();
+
+    std::fs::create_dir_all(dir.join("demo/src")).unwrap();
+    std::fs::write(dir.join("demo/Cargo.toml"), FEATURE_MANIFEST).unwrap();
+    std::fs::write(dir.join("demo/src/main.rs"), "fn main() {\n    println!(\"{}\", helper::extra());\n}\n").unwrap();
+}
+
+pub const FEATURE_MANIFEST: &str = "[package]\nname = \"demo\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[dependencies]\nhelper = { path = \"../helper\" }\n";
+
+/// Ответ модели на запрос `generate_cargo_fix` для воркспейса из `write_feature_workspace`.
+pub const FEATURE_REPLY: &str = r#"{"crate_name": "helper", "version": "0.1.0", "features": ["extra"]}"#;
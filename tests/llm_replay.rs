@@ -0,0 +1,76 @@
+//! Запись и воспроизведение ответов модели на настоящем сломанном крейте:
+//! фикстура, записанная с фейковым Ollama, должна чинить тот же крейт без сервера,
+//! в том числе скопированный в другой каталог.
+
+mod common;
+
+use std::path::Path;
+
+use common::{run_fixer, spawn_fake_ollama, write_feature_workspace, FEATURE_MANIFEST, FEATURE_REPLY};
+
+const BROKEN: &str = "fn main() {\n    let total: i32 = \"2\";\n    println!(\"{total}\");\n}\n";
+const FIXED: &str = "fn main() {\n    let total: i32 = 2;\n    println!(\"{total}\");\n}\n";
+
+const FIX_REPLY: &str = "<<<<<<< SEARCH\n    let total: i32 = \"2\";\n=======\n    let total: i32 = 2;\n>>>>>>> REPLACE\n";
+
+/// Адрес, на котором заведомо никто не слушает: при воспроизведении сеть не нужна.
+const NO_SERVER: &str = "http://127.0.0.1:9";
+
+fn write_crate(dir: &Path) {
+    std::fs::create_dir_all(dir.join("src")).unwrap();
+    std::fs::write(dir.join("Cargo.toml"), "[package]\nname = \"demo\"\nversion = \"0.1.0\"\nedition = \"2021\"\n\n[dependencies]\n").unwrap();
+    std::fs::write(dir.join("src/main.rs"), BROKEN).unwrap();
+}
+
+#[test]
+fn replayed_fixture_fixes_crate_without_server() {
+    let tmp = tempfile::tempdir().unwrap();
+    let project = tmp.path().join("demo");
+    let fixture = tmp.path().join("demo.jsonl");
+    let fixture_arg = fixture.to_str().unwrap();
+    write_crate(&project);
+
+    let url = spawn_fake_ollama(|_| FIX_REPLY.to_string());
+    run_fixer(&project, tmp.path(), &["--llm-backend", "ollama", "--llm-url", &url, "--llm-record", fixture_arg]);
+    assert_eq!(std::fs::read_to_string(project.join("src/main.rs")).unwrap(), FIXED);
+    assert!(!std::fs::read_to_string(&fixture).unwrap().trim().is_empty(), "the fix should come from the model");
+
+    // Без сервера: все ответы берутся из фикстуры, промпты должны совпасть байт в байт
+    std::fs::write(project.join("src/main.rs"), BROKEN).unwrap();
+    run_fixer(&project, tmp.path(), &["--llm-backend", "ollama", "--llm-url", NO_SERVER, "--llm-replay", fixture_arg]);
+    assert_eq!(std::fs::read_to_string(project.join("src/main.rs")).unwrap(), FIXED);
+
+    // С фикстурами кэш решений не используется: иначе второй прогон не дошёл бы до модели
+    let list = run_fixer(&project, tmp.path(), &["cache", "list"]);
+    assert!(String::from_utf8_lossy(&list.stdout).contains("The knowledge cache is empty."));
+
+    // Промпты не зависят от расположения проекта: та же фикстура чинит копию в другом каталоге
+    let other = tempfile::tempdir().unwrap();
+    let copy = other.path().join("elsewhere");
+    write_crate(&copy);
+    run_fixer(&copy, other.path(), &["--llm-backend", "ollama", "--llm-url", NO_SERVER, "--llm-replay", fixture_arg]);
+    assert_eq!(std::fs::read_to_string(copy.join("src/main.rs")).unwrap(), FIXED);
+}
+
+#[test]
+fn replayed_fixture_enables_missing_feature() {
+    let tmp = tempfile::tempdir().unwrap();
+    let fixture = tmp.path().join("feature.jsonl");
+    let fixture_arg = fixture.to_str().unwrap();
+    let recorded = tmp.path().join("recorded");
+    write_feature_workspace(&recorded);
+
+    let url = spawn_fake_ollama(|_| FEATURE_REPLY.to_string());
+    let project = recorded.join("demo");
+    run_fixer(&project, tmp.path(), &["--no-web", "--llm-backend", "ollama", "--llm-url", &url, "--llm-record", fixture_arg]);
+    let fixed = std::fs::read_to_string(project.join("Cargo.toml")).unwrap();
+    assert!(fixed.contains("features = [\"extra\"]"), "Cargo Expert should enable the feature:\n{fixed}");
+
+    // Воспроизведение на копии воркспейса в другом временном каталоге
+    let other = tempfile::tempdir().unwrap();
+    write_feature_workspace(other.path());
+    let copy = other.path().join("demo");
+    assert_eq!(std::fs::read_to_string(copy.join("Cargo.toml")).unwrap(), FEATURE_MANIFEST);
+    run_fixer(&copy, other.path(), &["--no-web", "--llm-backend", "ollama", "--llm-url", NO_SERVER, "--llm-replay", fixture_arg]);
+    assert_eq!(std::fs::read_to_string(copy.join("Cargo.toml")).unwrap(), fixed);
+}
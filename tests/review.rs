@@ -0,0 +1,37 @@
+//! Интерактивное ревью: правка, которую пользователь внёс в редакторе, проверяется
+//! заново и не попадает в проект, если она сломана.
+
+mod common;
+
+use std::io::Write;
+use std::process::Stdio;
+
+use common::{fixer, spawn_fake_ollama, write_feature_workspace, FEATURE_MANIFEST, FEATURE_REPLY};
+
+#[test]
+fn edited_broken_manifest_is_rejected() {
+    let tmp = tempfile::tempdir().unwrap();
+    write_feature_workspace(tmp.path());
+    let project = tmp.path().join("demo");
+    let url = spawn_fake_ollama(|_| FEATURE_REPLY.to_string());
+
+    // «Редактор» дописывает в начало манифеста строку, которую TOML не разберёт
+    let mut child = fixer(&project, tmp.path(), &["--interactive", "--no-web", "--llm-backend", "ollama", "--llm-url", &url])
+        .env("VISUAL", "sed -i 1i[[[")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // после ответа stdin закрывается: следующий вопрос остановит программу
+    child.stdin.take().unwrap().write_all(b"e\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(
+        stdout.contains("Edited manifest change failed verification"),
+        "the edited manifest should be re-verified:\n{stdout}\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(std::fs::read_to_string(project.join("Cargo.toml")).unwrap(), FEATURE_MANIFEST);
+}